@@ -0,0 +1,21 @@
+use std::process::Command;
+
+fn main() {
+    let commit_hash =
+        git(&["rev-parse", "--short", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+    let commit_date = git(&["log", "-1", "--date=short", "--pretty=format:%cd"])
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_COMMIT_HASH={}", commit_hash);
+    println!("cargo:rustc-env=GIT_COMMIT_DATE={}", commit_date);
+    println!("cargo:rerun-if-changed=../../.git/HEAD");
+    println!("cargo:rerun-if-changed=../../.git/refs");
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let s = String::from_utf8(output.stdout).ok()?;
+    Some(s.trim().to_string())
+}
@@ -0,0 +1,66 @@
+-- Baseline schema, the same as the api-server knex migrations up to
+-- 20221018014620_fix_log_index. Everything is created only if missing, so a
+-- database set up by knex is adopted as is.
+
+CREATE TABLE IF NOT EXISTS blocks (
+    number numeric NOT NULL,
+    hash bytea NOT NULL,
+    parent_hash bytea NOT NULL,
+    gas_limit numeric NOT NULL,
+    gas_used numeric NOT NULL,
+    miner bytea NOT NULL,
+    size integer NOT NULL,
+    "timestamp" timestamp with time zone NOT NULL,
+    CONSTRAINT blocks_pkey PRIMARY KEY (number)
+);
+CREATE UNIQUE INDEX IF NOT EXISTS blocks_hash_unique ON blocks (hash);
+
+CREATE TABLE IF NOT EXISTS transactions (
+    id bigserial NOT NULL,
+    hash bytea NOT NULL,
+    eth_tx_hash bytea NOT NULL,
+    block_number numeric NOT NULL,
+    block_hash bytea NOT NULL,
+    transaction_index integer NOT NULL,
+    from_address bytea NOT NULL,
+    to_address bytea,
+    value numeric(80, 0) NOT NULL,
+    nonce bigint NOT NULL,
+    gas_limit numeric,
+    gas_price numeric,
+    input bytea,
+    v smallint NOT NULL,
+    r bytea NOT NULL,
+    s bytea NOT NULL,
+    cumulative_gas_used numeric,
+    gas_used numeric,
+    contract_address bytea,
+    exit_code smallint NOT NULL,
+    CONSTRAINT transactions_pkey PRIMARY KEY (id)
+);
+CREATE UNIQUE INDEX IF NOT EXISTS transactions_hash_unique ON transactions (hash);
+CREATE UNIQUE INDEX IF NOT EXISTS transactions_eth_tx_hash_unique ON transactions (eth_tx_hash);
+CREATE INDEX IF NOT EXISTS transactions_from_address_index ON transactions (from_address);
+CREATE INDEX IF NOT EXISTS transactions_to_address_index ON transactions (to_address);
+CREATE INDEX IF NOT EXISTS transactions_contract_address_index ON transactions (contract_address);
+CREATE UNIQUE INDEX IF NOT EXISTS block_hash_transaction_index_idx ON transactions (block_hash, transaction_index);
+CREATE UNIQUE INDEX IF NOT EXISTS block_number_transaction_index_idx ON transactions (block_number, transaction_index);
+
+CREATE TABLE IF NOT EXISTS logs (
+    id bigserial NOT NULL,
+    transaction_id bigint NOT NULL,
+    transaction_hash bytea NOT NULL,
+    transaction_index integer NOT NULL,
+    block_number numeric NOT NULL,
+    block_hash bytea NOT NULL,
+    address bytea NOT NULL,
+    data bytea,
+    log_index integer NOT NULL,
+    topics bytea[] NOT NULL,
+    CONSTRAINT logs_pkey PRIMARY KEY (id)
+);
+CREATE INDEX IF NOT EXISTS logs_transaction_id_index ON logs (transaction_id);
+CREATE INDEX IF NOT EXISTS logs_transaction_hash_index ON logs (transaction_hash);
+CREATE INDEX IF NOT EXISTS logs_block_number_index ON logs (block_number);
+CREATE INDEX IF NOT EXISTS logs_block_hash_index ON logs (block_hash);
+CREATE INDEX IF NOT EXISTS logs_address_index ON logs (address);
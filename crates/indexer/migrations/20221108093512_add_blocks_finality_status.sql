@@ -0,0 +1,3 @@
+-- Same as the api-server knex migration 20221108093512_add_blocks_finality_status
+ALTER TABLE blocks ADD COLUMN IF NOT EXISTS finality_status varchar(255) NOT NULL DEFAULT 'unfinalized';
+CREATE INDEX IF NOT EXISTS blocks_unfinalized_number_idx ON blocks (number) WHERE finality_status = 'unfinalized';
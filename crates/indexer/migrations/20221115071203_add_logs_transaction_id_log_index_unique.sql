@@ -0,0 +1,2 @@
+-- Same as the api-server knex migration 20221115071203_add_logs_transaction_id_log_index_unique
+CREATE UNIQUE INDEX IF NOT EXISTS logs_transaction_id_log_index_unique ON logs (transaction_id, log_index);
@@ -0,0 +1,14 @@
+-- Same as the api-server knex migration 20221122021547_create_sync_state
+CREATE TABLE IF NOT EXISTS sync_state (
+    id smallint NOT NULL DEFAULT 1,
+    tip_number numeric NOT NULL,
+    tip_hash bytea NOT NULL,
+    updated_at timestamp with time zone NOT NULL,
+    indexer_version varchar(255),
+    chain_id bigint,
+    CONSTRAINT sync_state_pkey PRIMARY KEY (id),
+    CONSTRAINT sync_state_single_row CHECK (id = 1)
+);
+INSERT INTO sync_state (id, tip_number, tip_hash, updated_at)
+SELECT 1, number, hash, now() FROM blocks ORDER BY number DESC LIMIT 1
+ON CONFLICT (id) DO NOTHING;
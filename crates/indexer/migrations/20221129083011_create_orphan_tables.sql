@@ -0,0 +1,66 @@
+-- Same as the api-server knex migration 20221129083011_create_orphan_tables
+CREATE TABLE IF NOT EXISTS orphan_blocks (
+    orphan_id bigserial NOT NULL,
+    number numeric NOT NULL,
+    hash bytea NOT NULL,
+    parent_hash bytea NOT NULL,
+    gas_limit numeric NOT NULL,
+    gas_used numeric NOT NULL,
+    miner bytea NOT NULL,
+    size integer NOT NULL,
+    "timestamp" timestamp with time zone NOT NULL,
+    finality_status varchar(255) NOT NULL,
+    reverted_at timestamp with time zone NOT NULL,
+    replaced_by_hash bytea,
+    CONSTRAINT orphan_blocks_pkey PRIMARY KEY (orphan_id)
+);
+CREATE INDEX IF NOT EXISTS orphan_blocks_number_index ON orphan_blocks (number);
+CREATE INDEX IF NOT EXISTS orphan_blocks_hash_index ON orphan_blocks (hash);
+CREATE INDEX IF NOT EXISTS orphan_blocks_reverted_at_index ON orphan_blocks (reverted_at);
+
+CREATE TABLE IF NOT EXISTS orphan_transactions (
+    orphan_id bigserial NOT NULL,
+    id bigint NOT NULL,
+    hash bytea NOT NULL,
+    eth_tx_hash bytea NOT NULL,
+    block_number numeric NOT NULL,
+    block_hash bytea NOT NULL,
+    transaction_index integer NOT NULL,
+    from_address bytea NOT NULL,
+    to_address bytea,
+    value numeric(80, 0) NOT NULL,
+    nonce bigint NOT NULL,
+    gas_limit numeric,
+    gas_price numeric,
+    input bytea,
+    v smallint NOT NULL,
+    r bytea NOT NULL,
+    s bytea NOT NULL,
+    cumulative_gas_used numeric,
+    gas_used numeric,
+    contract_address bytea,
+    exit_code smallint NOT NULL,
+    reverted_at timestamp with time zone NOT NULL,
+    CONSTRAINT orphan_transactions_pkey PRIMARY KEY (orphan_id)
+);
+CREATE INDEX IF NOT EXISTS orphan_transactions_hash_index ON orphan_transactions (hash);
+CREATE INDEX IF NOT EXISTS orphan_transactions_eth_tx_hash_index ON orphan_transactions (eth_tx_hash);
+CREATE INDEX IF NOT EXISTS orphan_transactions_reverted_at_index ON orphan_transactions (reverted_at);
+
+CREATE TABLE IF NOT EXISTS orphan_logs (
+    orphan_id bigserial NOT NULL,
+    id bigint NOT NULL,
+    transaction_id bigint NOT NULL,
+    transaction_hash bytea NOT NULL,
+    transaction_index integer NOT NULL,
+    block_number numeric NOT NULL,
+    block_hash bytea NOT NULL,
+    address bytea NOT NULL,
+    data bytea,
+    log_index integer NOT NULL,
+    topics bytea[] NOT NULL,
+    reverted_at timestamp with time zone NOT NULL,
+    CONSTRAINT orphan_logs_pkey PRIMARY KEY (orphan_id)
+);
+CREATE INDEX IF NOT EXISTS orphan_logs_transaction_hash_index ON orphan_logs (transaction_hash);
+CREATE INDEX IF NOT EXISTS orphan_logs_reverted_at_index ON orphan_logs (reverted_at);
@@ -0,0 +1,8 @@
+-- Same as the api-server knex migration 20221206031402_create_prune_state
+CREATE TABLE IF NOT EXISTS prune_state (
+    id smallint NOT NULL DEFAULT 1,
+    pruned_below numeric NOT NULL,
+    updated_at timestamp with time zone NOT NULL,
+    CONSTRAINT prune_state_pkey PRIMARY KEY (id),
+    CONSTRAINT prune_state_single_row CHECK (id = 1)
+);
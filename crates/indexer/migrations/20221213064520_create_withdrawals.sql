@@ -0,0 +1,40 @@
+-- Same as the api-server knex migration 20221213064520_create_withdrawals
+CREATE TABLE IF NOT EXISTS withdrawals (
+    id bigserial NOT NULL,
+    block_number numeric NOT NULL,
+    block_hash bytea NOT NULL,
+    withdrawal_index integer NOT NULL,
+    account_script_hash bytea NOT NULL,
+    registry_id bigint NOT NULL,
+    address bytea,
+    amount numeric NOT NULL,
+    capacity numeric NOT NULL,
+    udt_script_hash bytea NOT NULL,
+    owner_lock_hash bytea NOT NULL,
+    nonce bigint NOT NULL,
+    CONSTRAINT withdrawals_pkey PRIMARY KEY (id),
+    CONSTRAINT withdrawals_block_number_withdrawal_index_unique UNIQUE (block_number, withdrawal_index)
+);
+CREATE INDEX IF NOT EXISTS withdrawals_block_hash_index ON withdrawals (block_hash);
+CREATE INDEX IF NOT EXISTS withdrawals_account_script_hash_index ON withdrawals (account_script_hash);
+CREATE INDEX IF NOT EXISTS withdrawals_address_index ON withdrawals (address);
+CREATE INDEX IF NOT EXISTS withdrawals_owner_lock_hash_index ON withdrawals (owner_lock_hash);
+
+CREATE TABLE IF NOT EXISTS orphan_withdrawals (
+    orphan_id bigserial NOT NULL,
+    id bigint NOT NULL,
+    block_number numeric NOT NULL,
+    block_hash bytea NOT NULL,
+    withdrawal_index integer NOT NULL,
+    account_script_hash bytea NOT NULL,
+    registry_id bigint NOT NULL,
+    address bytea,
+    amount numeric NOT NULL,
+    capacity numeric NOT NULL,
+    udt_script_hash bytea NOT NULL,
+    owner_lock_hash bytea NOT NULL,
+    nonce bigint NOT NULL,
+    reverted_at timestamp with time zone NOT NULL,
+    CONSTRAINT orphan_withdrawals_pkey PRIMARY KEY (orphan_id)
+);
+CREATE INDEX IF NOT EXISTS orphan_withdrawals_reverted_at_index ON orphan_withdrawals (reverted_at);
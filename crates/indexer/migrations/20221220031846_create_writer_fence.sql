@@ -0,0 +1,8 @@
+-- Same as the api-server knex migration 20221220031846_create_writer_fence
+CREATE TABLE IF NOT EXISTS writer_fence (
+    id smallint NOT NULL DEFAULT 1,
+    token bigint NOT NULL,
+    updated_at timestamp with time zone NOT NULL,
+    CONSTRAINT writer_fence_pkey PRIMARY KEY (id),
+    CONSTRAINT writer_fence_single_row CHECK (id = 1)
+);
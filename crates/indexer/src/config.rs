@@ -1,132 +1,621 @@
-use std::{env, fmt, fmt::Display, path::Path};
+use std::{env, fmt, fmt::Display, fs, path::Path, str::FromStr, time::Duration};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use ckb_types::H256;
-use dotenv;
 use gw_jsonrpc_types::godwoken::{BackendType, EoaScriptType, GwScriptType};
-use gw_web3_rpc_client::godwoken_rpc_client::GodwokenRpcClient;
+use gw_web3_rpc_client::godwoken_async_client::GodwokenAsyncClient;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+use crate::{insert_l2_block::InsertMode, secret::Secret};
+
+pub const DEFAULT_GODWOKEN_RPC_URL: &str = "http://127.0.0.1:8119";
+pub const DEFAULT_PREFETCH_WINDOW: usize = 8;
+pub const DEFAULT_MAX_REORG_DEPTH: u64 = 256;
+pub const DEFAULT_RETRY_MAX_RETRIES: u32 = 10;
+pub const DEFAULT_RETRY_INITIAL_INTERVAL_MS: u64 = 1000;
+pub const DEFAULT_RETRY_MAX_INTERVAL_MS: u64 = 60_000;
+pub const DEFAULT_FINALITY_CHECK_INTERVAL_SECS: u64 = 30;
+pub const DEFAULT_BLOCK_POLL_INTERVAL_MS: u64 = 1000;
+pub const DEFAULT_ORPHAN_RETENTION_DAYS: u64 = 30;
+pub const DEFAULT_PRUNE_BATCH_BLOCKS: u64 = 1000;
+
+pub const DEFAULT_PG_MAX_CONNECTIONS: u32 = 5;
+pub const DEFAULT_PG_ACQUIRE_TIMEOUT_SECS: u64 = 30;
+pub const DEFAULT_PG_IDLE_TIMEOUT_SECS: u64 = 600;
+pub const DEFAULT_PG_LOG_STATEMENTS: &str = "debug";
+pub const DEFAULT_PG_SLOW_STATEMENT_THRESHOLD_MS: u64 = 5000;
+
+const MAX_ORPHAN_RETENTION_DAYS: u64 = 36_500;
+const NODE_INFO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Indexer config, loaded from a TOML file whose keys are the field names.
+///
+/// Every field can be overridden by an environment variable named after it, in upper
+/// case (`PG_URL`) or, for compatibility, in lower case (`pg_url`). Chain spec fields
+/// left unset are loaded from `gw_get_node_info` by [`resolve_chain_spec`], see [`ChainSpec`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct IndexerConfig {
-    pub l2_sudt_type_script_hash: H256,
-    pub polyjuice_type_script_hash: H256,
-    pub rollup_type_hash: H256,
-    pub eth_account_lock_hash: H256,
+    pub l2_sudt_type_script_hash: Option<H256>,
+    pub polyjuice_type_script_hash: Option<H256>,
+    pub rollup_type_hash: Option<H256>,
+    pub eth_account_lock_hash: Option<H256>,
     pub godwoken_rpc_url: String,
-    pub pg_url: String,
-    pub chain_id: u64,
-    pub sentry_dsn: Option<String>,
+    pub pg_url: Secret,
+    pub pg_max_connections: u32,
+    pub pg_acquire_timeout_secs: u64,
+    // 0 keeps idle connections forever
+    pub pg_idle_timeout_secs: u64,
+    // Log level of executed statements, e.g. "debug" or "off"
+    pub pg_log_statements: String,
+    pub pg_slow_statement_threshold_ms: u64,
+    // Index into SQLite instead of Postgres, e.g. "sqlite://indexer.db", needs the
+    // sqlite feature
+    pub sqlite_url: Option<String>,
+    pub chain_id: Option<u64>,
+    pub sentry_dsn: Option<Secret>,
     pub sentry_environment: Option<String>,
+    pub prefetch_window: usize,
+    pub max_reorg_depth: u64,
+    pub start_block_number: Option<u64>,
+    pub start_block_hash: Option<H256>,
+    pub backfill_from: Option<u64>,
+    pub backfill_to: Option<u64>,
+    pub writer_lock: bool,
+    // "values" or "unnest", the latter is faster for blocks with many logs
+    pub insert_mode: InsertMode,
+    // Retries of a block when backfilling, the live indexer retries transient errors
+    // until they go away
+    pub retry_max_retries: u32,
+    pub retry_initial_interval_ms: u64,
+    pub retry_max_interval_ms: u64,
+    // 0 disables finality tracking
+    pub finality_check_interval_secs: u64,
+    // Wait between fetches of the next block once the tip is reached
+    pub block_poll_interval_ms: u64,
+    // Also poll the cheaper tip hash at this interval and fetch the next block as soon as it
+    // changes. Godwoken has no push notification of new blocks
+    pub tip_poll_interval_ms: Option<u64>,
+    // Days to keep reverted blocks in the orphan tables, 0 keeps them forever
+    pub orphan_retention_days: u64,
+    // Keep logs and transaction inputs of the latest blocks only, 0 disables pruning
+    pub prune_keep_blocks: u64,
+    // Blocks pruned by one db transaction
+    pub prune_batch_blocks: u64,
+}
+
+impl Default for IndexerConfig {
+    fn default() -> Self {
+        IndexerConfig {
+            l2_sudt_type_script_hash: None,
+            polyjuice_type_script_hash: None,
+            rollup_type_hash: None,
+            eth_account_lock_hash: None,
+            godwoken_rpc_url: DEFAULT_GODWOKEN_RPC_URL.to_string(),
+            pg_url: Secret::default(),
+            pg_max_connections: DEFAULT_PG_MAX_CONNECTIONS,
+            pg_acquire_timeout_secs: DEFAULT_PG_ACQUIRE_TIMEOUT_SECS,
+            pg_idle_timeout_secs: DEFAULT_PG_IDLE_TIMEOUT_SECS,
+            pg_log_statements: DEFAULT_PG_LOG_STATEMENTS.to_string(),
+            pg_slow_statement_threshold_ms: DEFAULT_PG_SLOW_STATEMENT_THRESHOLD_MS,
+            sqlite_url: None,
+            chain_id: None,
+            sentry_dsn: None,
+            sentry_environment: None,
+            prefetch_window: DEFAULT_PREFETCH_WINDOW,
+            max_reorg_depth: DEFAULT_MAX_REORG_DEPTH,
+            start_block_number: None,
+            start_block_hash: None,
+            backfill_from: None,
+            backfill_to: None,
+            writer_lock: true,
+            insert_mode: InsertMode::default(),
+            retry_max_retries: DEFAULT_RETRY_MAX_RETRIES,
+            retry_initial_interval_ms: DEFAULT_RETRY_INITIAL_INTERVAL_MS,
+            retry_max_interval_ms: DEFAULT_RETRY_MAX_INTERVAL_MS,
+            finality_check_interval_secs: DEFAULT_FINALITY_CHECK_INTERVAL_SECS,
+            block_poll_interval_ms: DEFAULT_BLOCK_POLL_INTERVAL_MS,
+            tip_poll_interval_ms: None,
+            orphan_retention_days: DEFAULT_ORPHAN_RETENTION_DAYS,
+            prune_keep_blocks: 0,
+            prune_batch_blocks: DEFAULT_PRUNE_BATCH_BLOCKS,
+        }
+    }
 }
 
+// Compact JSON, secrets are redacted
 impl Display for IndexerConfig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "IndexerConfig {{ ")?;
-        write!(
-            f,
-            "l2_sudt_type_script_hash: 0x{}, ",
-            self.l2_sudt_type_script_hash
-        )?;
-        write!(
-            f,
-            "polyjuice_type_script_hash: 0x{}, ",
-            self.polyjuice_type_script_hash
-        )?;
-        write!(f, "rollup_type_hash: 0x{}, ", self.rollup_type_hash)?;
-        write!(
-            f,
-            "eth_account_lock_hash: 0x{}, ",
-            self.eth_account_lock_hash
-        )?;
-        write!(f, "godwoken_rpc_url: {}, ", self.godwoken_rpc_url)?;
-        write!(f, "pg_url: {}", self.pg_url)?;
-        write!(f, "chain_id: {}", self.chain_id)?;
-        if let Some(t) = &self.sentry_dsn {
-            write!(f, "sentry_dsn: {}, ", t)?;
-        } else {
-            write!(f, "sentry_dsn: null, ")?;
-        }
-        if let Some(t) = &self.sentry_environment {
-            write!(f, "sentry_environment: {}, ", t)?;
-        } else {
-            write!(f, "sentry_environment: null, ")?;
-        }
-        write!(f, " }}")
-    }
-}
-
-// TODO: rename the configuration file from "indexer-config.toml" to ".env"
-// TODO: uppercase the environment variables name "pg_url", "godwoken_rpc_url", etc
+        let json = serde_json::to_string(self).map_err(|_| fmt::Error)?;
+        write!(f, "{}", json)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("read config file {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+
+    #[error("parse config file {path}: {source}")]
+    Parse {
+        path: String,
+        source: toml::de::Error,
+    },
+
+    #[error("invalid config:\n  - {}", .0.join("\n  - "))]
+    Invalid(Vec<String>),
+}
+
+/// Loads and validates the config without contacting Godwoken, commands which need the
+/// chain spec call [`resolve_chain_spec`] afterwards.
 pub fn load_indexer_config<P: AsRef<Path>>(path: P) -> Result<IndexerConfig> {
-    if path.as_ref().exists() {
-        log::info!(
-            "Loading configuration file {}",
-            path.as_ref().to_string_lossy().to_string()
-        );
-        dotenv::from_path(path)?;
+    let path_str = path.as_ref().to_string_lossy().to_string();
+    let mut config = if path.as_ref().exists() {
+        log::info!("Loading configuration file {}", path_str);
+        let content = fs::read_to_string(&path).map_err(|source| ConfigError::Read {
+            path: path_str.clone(),
+            source,
+        })?;
+        toml::from_str(&content).map_err(|source| ConfigError::Parse {
+            path: path_str,
+            source,
+        })?
     } else {
-        log::info!(
-            "Cannot find configuration file {}, continue",
-            path.as_ref().to_string_lossy().to_string()
-        );
+        log::info!("Cannot find configuration file {}, continue", path_str);
+        IndexerConfig::default()
+    };
+
+    let mut errors = override_from_env(&mut config);
+    errors.extend(config.validate());
+    if !errors.is_empty() {
+        return Err(ConfigError::Invalid(errors).into());
+    }
+    Ok(config)
+}
+
+/// Scripts and ids of the rollup, resolved from config and `gw_get_node_info`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainSpec {
+    pub l2_sudt_type_script_hash: H256,
+    pub polyjuice_type_script_hash: H256,
+    pub rollup_type_hash: H256,
+    pub eth_account_lock_hash: H256,
+    pub chain_id: u64,
+}
+
+impl IndexerConfig {
+    /// Chain spec of a loaded config, errors if any field is still unset.
+    pub fn chain_spec(&self) -> Result<ChainSpec> {
+        let missing = |field: &str| anyhow!("chain spec field \"{}\" is not set", field);
+        Ok(ChainSpec {
+            l2_sudt_type_script_hash: self
+                .l2_sudt_type_script_hash
+                .clone()
+                .ok_or_else(|| missing("l2_sudt_type_script_hash"))?,
+            polyjuice_type_script_hash: self
+                .polyjuice_type_script_hash
+                .clone()
+                .ok_or_else(|| missing("polyjuice_type_script_hash"))?,
+            rollup_type_hash: self
+                .rollup_type_hash
+                .clone()
+                .ok_or_else(|| missing("rollup_type_hash"))?,
+            eth_account_lock_hash: self
+                .eth_account_lock_hash
+                .clone()
+                .ok_or_else(|| missing("eth_account_lock_hash"))?,
+            chain_id: self.chain_id.ok_or_else(|| missing("chain_id"))?,
+        })
+    }
+
+    /// Returns every problem found, empty if the config is valid.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.pg_url.is_empty() && self.sqlite_url.is_none() {
+            errors.push("\"pg_url\" is required".to_string());
+        }
+        if self.pg_max_connections == 0 {
+            errors.push("\"pg_max_connections\" must be greater than 0".to_string());
+        }
+        if log::LevelFilter::from_str(&self.pg_log_statements).is_err() {
+            errors.push(format!(
+                "\"pg_log_statements\" {:?} is not a log level",
+                self.pg_log_statements
+            ));
+        }
+        if self.godwoken_rpc_url.is_empty() {
+            errors.push("\"godwoken_rpc_url\" is required".to_string());
+        }
+        if self.backfill_from.is_some() != self.backfill_to.is_some() {
+            errors.push("\"backfill_from\" and \"backfill_to\" must be set together".to_string());
+        }
+        if let (Some(from), Some(to)) = (self.backfill_from, self.backfill_to) {
+            if from > to {
+                errors.push(format!(
+                    "\"backfill_from\" {} is greater than \"backfill_to\" {}",
+                    from, to
+                ));
+            }
+        }
+        if self.start_block_hash.is_some() && self.start_block_number.is_none() {
+            errors.push("\"start_block_hash\" requires \"start_block_number\"".to_string());
+        }
+        if self.retry_initial_interval_ms > self.retry_max_interval_ms {
+            errors.push(
+                "\"retry_initial_interval_ms\" must not exceed \"retry_max_interval_ms\""
+                    .to_string(),
+            );
+        }
+        if self.block_poll_interval_ms == 0 {
+            errors.push("\"block_poll_interval_ms\" must be greater than 0".to_string());
+        }
+        if self.tip_poll_interval_ms == Some(0) {
+            errors.push("\"tip_poll_interval_ms\" must be greater than 0".to_string());
+        }
+        if self.orphan_retention_days > MAX_ORPHAN_RETENTION_DAYS {
+            errors.push(format!(
+                "\"orphan_retention_days\" must not exceed {}, 0 keeps orphans forever",
+                MAX_ORPHAN_RETENTION_DAYS
+            ));
+        }
+        // Rolling back a pruned block would archive it without its logs
+        if self.prune_keep_blocks != 0 && self.prune_keep_blocks <= self.max_reorg_depth {
+            errors.push(format!(
+                "\"prune_keep_blocks\" must be greater than \"max_reorg_depth\" {}, 0 disables pruning",
+                self.max_reorg_depth
+            ));
+        }
+        if self.prune_batch_blocks == 0 {
+            errors.push("\"prune_batch_blocks\" must be greater than 0".to_string());
+        }
+        errors
+    }
+}
+
+// Returns the env vars which failed to parse
+fn override_from_env(config: &mut IndexerConfig) -> Vec<String> {
+    let mut errors = vec![];
+    let e = &mut errors;
+    env_override_hash(
+        &mut config.l2_sudt_type_script_hash,
+        "l2_sudt_type_script_hash",
+        e,
+    );
+    env_override_hash(
+        &mut config.polyjuice_type_script_hash,
+        "polyjuice_type_script_hash",
+        e,
+    );
+    env_override_hash(&mut config.rollup_type_hash, "rollup_type_hash", e);
+    env_override_hash(
+        &mut config.eth_account_lock_hash,
+        "eth_account_lock_hash",
+        e,
+    );
+    env_override_opt(&mut config.chain_id, "chain_id", e);
+    env_override(&mut config.godwoken_rpc_url, "godwoken_rpc_url", e);
+    env_override(&mut config.pg_url, "pg_url", e);
+    env_override(&mut config.pg_max_connections, "pg_max_connections", e);
+    env_override(
+        &mut config.pg_acquire_timeout_secs,
+        "pg_acquire_timeout_secs",
+        e,
+    );
+    env_override(&mut config.pg_idle_timeout_secs, "pg_idle_timeout_secs", e);
+    env_override(&mut config.pg_log_statements, "pg_log_statements", e);
+    env_override(
+        &mut config.pg_slow_statement_threshold_ms,
+        "pg_slow_statement_threshold_ms",
+        e,
+    );
+    env_override_opt(&mut config.sqlite_url, "sqlite_url", e);
+    env_override_opt(&mut config.sentry_dsn, "sentry_dsn", e);
+    env_override_opt(&mut config.sentry_environment, "sentry_environment", e);
+    env_override(&mut config.prefetch_window, "prefetch_window", e);
+    env_override(&mut config.max_reorg_depth, "max_reorg_depth", e);
+    env_override_opt(&mut config.start_block_number, "start_block_number", e);
+    env_override_hash(&mut config.start_block_hash, "start_block_hash", e);
+    env_override_opt(&mut config.backfill_from, "backfill_from", e);
+    env_override_opt(&mut config.backfill_to, "backfill_to", e);
+    env_override(&mut config.writer_lock, "writer_lock", e);
+    env_override(&mut config.insert_mode, "insert_mode", e);
+    env_override(&mut config.retry_max_retries, "retry_max_retries", e);
+    env_override(
+        &mut config.retry_initial_interval_ms,
+        "retry_initial_interval_ms",
+        e,
+    );
+    env_override(
+        &mut config.retry_max_interval_ms,
+        "retry_max_interval_ms",
+        e,
+    );
+    env_override(
+        &mut config.finality_check_interval_secs,
+        "finality_check_interval_secs",
+        e,
+    );
+    env_override(
+        &mut config.block_poll_interval_ms,
+        "block_poll_interval_ms",
+        e,
+    );
+    env_override_opt(&mut config.tip_poll_interval_ms, "tip_poll_interval_ms", e);
+    env_override(
+        &mut config.orphan_retention_days,
+        "orphan_retention_days",
+        e,
+    );
+    env_override(&mut config.prune_keep_blocks, "prune_keep_blocks", e);
+    env_override(&mut config.prune_batch_blocks, "prune_batch_blocks", e);
+    errors
+}
+
+// Upper case name first, then the legacy lower case one
+fn env_var(field: &str) -> Option<(String, String)> {
+    let upper = field.to_uppercase();
+    env::var(&upper)
+        .map(|v| (upper, v))
+        .or_else(|_| env::var(field).map(|v| (field.to_string(), v)))
+        .ok()
+}
+
+fn env_override<T>(value: &mut T, field: &str, errors: &mut Vec<String>)
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Some((name, v)) = env_var(field) {
+        match v.parse() {
+            Ok(v) => *value = v,
+            Err(err) => errors.push(format!("env var \"{}\": {}", name, err)),
+        }
+    }
+}
+
+fn env_override_opt<T>(value: &mut Option<T>, field: &str, errors: &mut Vec<String>)
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Some((name, v)) = env_var(field) {
+        match v.parse() {
+            Ok(v) => *value = Some(v),
+            Err(err) => errors.push(format!("env var \"{}\": {}", name, err)),
+        }
+    }
+}
+
+// 0x prefixed hex, the same format as in the config file
+fn env_override_hash(value: &mut Option<H256>, field: &str, errors: &mut Vec<String>) {
+    if let Some((name, v)) = env_var(field) {
+        match serde_json::from_value(serde_json::Value::String(v)) {
+            Ok(h) => *value = Some(h),
+            Err(err) => errors.push(format!("env var \"{}\": {}", name, err)),
+        }
     }
+}
 
-    // Load components configurations from environment variables
-    let pg_url = env::var("pg_url").expect("env var \"pg_url\" is required");
-    let godwoken_rpc_url =
-        env::var("godwoken_rpc_url").unwrap_or_else(|_| "http://127.0.0.1:8119".to_string());
-    let sentry_dsn = env::var("sentry_dsn").ok();
-    let sentry_environment = env::var("sentry_environment").ok();
+/// Fill unset chain spec fields from `gw_get_node_info` and cross-check the configured
+/// ones. Godwoken may be down if every field is configured, the check is skipped then.
+pub async fn resolve_chain_spec(config: &mut IndexerConfig) -> Result<()> {
+    let node_chain_spec = fetch_chain_spec(&config.godwoken_rpc_url).await;
+    let node_chain_spec = match (config.chain_spec(), node_chain_spec) {
+        (_, Ok(node_chain_spec)) => node_chain_spec,
+        (Ok(_), Err(err)) => {
+            log::warn!("Skip chain spec check, gw_get_node_info failed: {}", err);
+            return Ok(());
+        }
+        (Err(_), Err(err)) => {
+            return Err(err.context("chain spec is not fully configured, load gw_get_node_info"))
+        }
+    };
 
-    // Load chain spec via gw_get_node_info
-    let godwoken_rpc_client = GodwokenRpcClient::new(&godwoken_rpc_url);
-    let godwoken_node_info = godwoken_rpc_client.get_node_info()?;
-    let l2_sudt_type_script_hash = godwoken_node_info
+    let mut errors = vec![];
+    let e = &mut errors;
+    let hex = |h: &H256| format!("0x{}", h);
+    resolve_field(
+        &mut config.l2_sudt_type_script_hash,
+        node_chain_spec.l2_sudt_type_script_hash,
+        "l2_sudt_type_script_hash",
+        hex,
+        e,
+    );
+    resolve_field(
+        &mut config.polyjuice_type_script_hash,
+        node_chain_spec.polyjuice_type_script_hash,
+        "polyjuice_type_script_hash",
+        hex,
+        e,
+    );
+    resolve_field(
+        &mut config.rollup_type_hash,
+        node_chain_spec.rollup_type_hash,
+        "rollup_type_hash",
+        hex,
+        e,
+    );
+    resolve_field(
+        &mut config.eth_account_lock_hash,
+        node_chain_spec.eth_account_lock_hash,
+        "eth_account_lock_hash",
+        hex,
+        e,
+    );
+    resolve_field(
+        &mut config.chain_id,
+        node_chain_spec.chain_id,
+        "chain_id",
+        u64::to_string,
+        e,
+    );
+    if !errors.is_empty() {
+        return Err(ConfigError::Invalid(errors).into());
+    }
+
+    Ok(())
+}
+
+fn resolve_field<T: PartialEq>(
+    value: &mut Option<T>,
+    node_value: T,
+    field: &str,
+    to_string: impl Fn(&T) -> String,
+    errors: &mut Vec<String>,
+) {
+    match value {
+        Some(v) if *v != node_value => errors.push(format!(
+            "\"{}\" {} mismatches gw_get_node_info {}",
+            field,
+            to_string(v),
+            to_string(&node_value)
+        )),
+        Some(_) => {}
+        None => *value = Some(node_value),
+    }
+}
+
+async fn fetch_chain_spec(godwoken_rpc_url: &str) -> Result<ChainSpec> {
+    let godwoken_async_client = GodwokenAsyncClient::with_url(godwoken_rpc_url)?;
+    let node_info = smol::future::or(
+        async { Some(godwoken_async_client.get_node_info().await) },
+        async {
+            smol::Timer::after(NODE_INFO_TIMEOUT).await;
+            None
+        },
+    )
+    .await
+    .ok_or_else(|| anyhow!("gw_get_node_info timed out after {:?}", NODE_INFO_TIMEOUT))??;
+
+    let mut errors = vec![];
+    let l2_sudt_type_script_hash = node_info
         .gw_scripts
         .iter()
-        .find_map(|gw_script| {
-            if gw_script.script_type == GwScriptType::L2Sudt {
-                Some(gw_script.type_hash.clone())
-            } else {
-                None
-            }
-        })
-        .unwrap();
-    let polyjuice_type_script_hash = godwoken_node_info
+        .find(|gw_script| gw_script.script_type == GwScriptType::L2Sudt)
+        .map(|gw_script| gw_script.type_hash.clone());
+    if l2_sudt_type_script_hash.is_none() {
+        errors
+            .push("\"l2_sudt_type_script_hash\": no L2Sudt script in gw_get_node_info".to_string());
+    }
+    let polyjuice_type_script_hash = node_info
         .backends
         .iter()
-        .find_map(|backend_info| {
-            if backend_info.backend_type == BackendType::Polyjuice {
-                Some(backend_info.validator_script_type_hash.clone())
-            } else {
-                None
-            }
-        })
-        .unwrap();
-    let eth_account_lock_hash = godwoken_node_info
+        .find(|backend_info| backend_info.backend_type == BackendType::Polyjuice)
+        .map(|backend_info| backend_info.validator_script_type_hash.clone());
+    if polyjuice_type_script_hash.is_none() {
+        errors.push(
+            "\"polyjuice_type_script_hash\": no Polyjuice backend in gw_get_node_info".to_string(),
+        );
+    }
+    let eth_account_lock_hash = node_info
         .eoa_scripts
         .iter()
-        .find_map(|eoa_script| {
-            if eoa_script.eoa_type == EoaScriptType::Eth {
-                Some(eoa_script.type_hash.clone())
-            } else {
-                None
-            }
-        })
-        .unwrap();
-    let rollup_type_hash = godwoken_node_info.rollup_cell.type_hash.clone();
-    let chain_id = godwoken_node_info.rollup_config.chain_id.value();
+        .find(|eoa_script| eoa_script.eoa_type == EoaScriptType::Eth)
+        .map(|eoa_script| eoa_script.type_hash.clone());
+    if eth_account_lock_hash.is_none() {
+        errors.push("\"eth_account_lock_hash\": no Eth EOA script in gw_get_node_info".to_string());
+    }
 
-    Ok(IndexerConfig {
+    match (
         l2_sudt_type_script_hash,
         polyjuice_type_script_hash,
-        rollup_type_hash,
         eth_account_lock_hash,
-        godwoken_rpc_url,
-        pg_url,
-        chain_id,
-        sentry_dsn,
-        sentry_environment,
-    })
+    ) {
+        (
+            Some(l2_sudt_type_script_hash),
+            Some(polyjuice_type_script_hash),
+            Some(eth_account_lock_hash),
+        ) => Ok(ChainSpec {
+            l2_sudt_type_script_hash,
+            polyjuice_type_script_hash,
+            rollup_type_hash: node_info.rollup_cell.type_hash.clone(),
+            eth_account_lock_hash,
+            chain_id: node_info.rollup_config.chain_id.value(),
+        }),
+        _ => Err(ConfigError::Invalid(errors).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid_config() -> IndexerConfig {
+        IndexerConfig {
+            pg_url: Secret::new("postgres://u:p@localhost/db".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validate_valid_config() {
+        assert!(valid_config().validate().is_empty());
+
+        let config = IndexerConfig {
+            sqlite_url: Some("sqlite::memory:".to_string()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_empty());
+    }
+
+    #[test]
+    fn validate_reports_every_error() {
+        let config = IndexerConfig {
+            pg_url: Secret::default(),
+            pg_max_connections: 0,
+            pg_log_statements: "loud".to_string(),
+            godwoken_rpc_url: String::new(),
+            backfill_from: Some(10),
+            backfill_to: Some(5),
+            block_poll_interval_ms: 0,
+            prune_keep_blocks: DEFAULT_MAX_REORG_DEPTH,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate(),
+            vec![
+                "\"pg_url\" is required".to_string(),
+                "\"pg_max_connections\" must be greater than 0".to_string(),
+                "\"pg_log_statements\" \"loud\" is not a log level".to_string(),
+                "\"godwoken_rpc_url\" is required".to_string(),
+                "\"backfill_from\" 10 is greater than \"backfill_to\" 5".to_string(),
+                "\"block_poll_interval_ms\" must be greater than 0".to_string(),
+                format!(
+                    "\"prune_keep_blocks\" must be greater than \"max_reorg_depth\" {}, 0 disables pruning",
+                    DEFAULT_MAX_REORG_DEPTH
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_paired_fields() {
+        let config = IndexerConfig {
+            backfill_from: Some(1),
+            start_block_hash: Some(H256::default()),
+            retry_initial_interval_ms: 2000,
+            retry_max_interval_ms: 1000,
+            tip_poll_interval_ms: Some(0),
+            ..valid_config()
+        };
+        assert_eq!(
+            config.validate(),
+            vec![
+                "\"backfill_from\" and \"backfill_to\" must be set together",
+                "\"start_block_hash\" requires \"start_block_number\"",
+                "\"retry_initial_interval_ms\" must not exceed \"retry_max_interval_ms\"",
+                "\"tip_poll_interval_ms\" must be greater than 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_config_error_lists_every_error() {
+        let err = ConfigError::Invalid(vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(err.to_string(), "invalid config:\n  - a\n  - b");
+    }
 }
@@ -0,0 +1,182 @@
+use std::{cmp, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use ckb_types::H256;
+use gw_jsonrpc_types::godwoken::L2BlockStatus;
+use gw_web3_rpc_client::godwoken_async_client::GodwokenAsyncClient;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use sqlx::PgPool;
+
+pub const FINALITY_UNFINALIZED: &str = "unfinalized";
+pub const FINALITY_FINALIZED: &str = "finalized";
+pub const FINALITY_REVERTED: &str = "reverted";
+
+// Max blocks finalized by one update statement
+const FINALIZE_BATCH_SIZE: u64 = 10_000;
+
+/// Move indexed blocks to finalized as layer 1 confirms them.
+///
+/// Blocks are finalized in order, so the finalized blocks are always a prefix of
+/// the indexed ones and the highest finalized block can be found by a binary search
+/// over the unfinalized ones.
+pub struct FinalityTracker {
+    godwoken_async_client: Arc<GodwokenAsyncClient>,
+    pool: PgPool,
+    interval: Duration,
+}
+
+impl FinalityTracker {
+    pub fn new(
+        godwoken_async_client: Arc<GodwokenAsyncClient>,
+        pool: PgPool,
+        interval: Duration,
+    ) -> Self {
+        FinalityTracker {
+            godwoken_async_client,
+            pool,
+            interval,
+        }
+    }
+
+    pub async fn run(self) {
+        loop {
+            if let Err(err) = self.update().await {
+                log::error!("Update block finality failed: {}", err);
+            }
+            smol::Timer::after(self.interval).await;
+        }
+    }
+
+    /// Returns the highest finalized block number found in this round.
+    pub async fn update(&self) -> Result<Option<u64>> {
+        let (lowest, highest) = match get_unfinalized_range(&self.pool).await? {
+            Some(range) => range,
+            None => return Ok(None),
+        };
+
+        // Find the highest finalized block in [lowest, highest]
+        let mut finalized: Option<(u64, H256)> = None;
+        let (mut lo, mut hi) = (lowest, highest);
+        while lo <= hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.get_status(mid).await? {
+                Some((block_hash, L2BlockStatus::Finalized)) => {
+                    finalized = Some((mid, block_hash));
+                    lo = mid + 1;
+                }
+                _ => {
+                    if mid == 0 {
+                        break;
+                    }
+                    hi = mid - 1;
+                }
+            }
+        }
+
+        let first_unfinalized = match finalized {
+            Some((number, block_hash)) => {
+                finalize_blocks(&self.pool, lowest, number, &block_hash).await?;
+                number + 1
+            }
+            None => lowest,
+        };
+        if first_unfinalized <= highest {
+            if let Some((block_hash, L2BlockStatus::Reverted)) =
+                self.get_status(first_unfinalized).await?
+            {
+                // Descendants of a reverted block are reverted too, they will be
+                // rolled back once the chain moves on
+                if revert_blocks_from(&self.pool, first_unfinalized, &block_hash).await? > 0 {
+                    log::warn!("Blocks from {} are reverted on layer 1", first_unfinalized);
+                }
+            }
+        }
+
+        Ok(finalized.map(|(number, _)| number))
+    }
+
+    async fn get_status(&self, block_number: u64) -> Result<Option<(H256, L2BlockStatus)>> {
+        let block_hash = match get_db_block_hash(&self.pool, block_number).await? {
+            Some(h) => h,
+            None => return Ok(None),
+        };
+        let block = self.godwoken_async_client.get_block(&block_hash).await?;
+        Ok(block.map(|b| (block_hash, b.status)))
+    }
+}
+
+// Lowest unfinalized and highest indexed block numbers
+async fn get_unfinalized_range(pool: &PgPool) -> Result<Option<(u64, u64)>> {
+    let row: (Option<Decimal>, Option<Decimal>) = sqlx::query_as(
+        "SELECT (SELECT MIN(number) FROM blocks WHERE finality_status = $1), (SELECT MAX(number) FROM blocks)",
+    )
+    .bind(FINALITY_UNFINALIZED)
+    .fetch_one(pool)
+    .await?;
+
+    match row {
+        (Some(lowest), Some(highest)) => Ok(lowest.to_u64().zip(highest.to_u64())),
+        _ => Ok(None),
+    }
+}
+
+async fn get_db_block_hash(pool: &PgPool, block_number: u64) -> Result<Option<H256>> {
+    let row: Option<(Vec<u8>,)> = sqlx::query_as("SELECT hash FROM blocks WHERE number = $1")
+        .bind(Decimal::from(block_number))
+        .fetch_optional(pool)
+        .await?;
+    match row {
+        Some((hash,)) => Ok(Some(H256::from_slice(&hash)?)),
+        None => Ok(None),
+    }
+}
+
+// Finalize [from, to] in batches, stop if block `to` was replaced in the meantime
+async fn finalize_blocks(
+    pool: &PgPool,
+    from_block_number: u64,
+    to_block_number: u64,
+    to_hash: &H256,
+) -> Result<()> {
+    let mut batch_start = from_block_number;
+    while batch_start <= to_block_number {
+        let batch_end = cmp::min(to_block_number, batch_start + FINALIZE_BATCH_SIZE - 1);
+        let result = sqlx::query(
+            "UPDATE blocks SET finality_status = $1 WHERE number >= $2 AND number <= $3 AND finality_status <> $1 AND EXISTS (SELECT 1 FROM blocks WHERE number = $4 AND hash = $5)",
+        )
+        .bind(FINALITY_FINALIZED)
+        .bind(Decimal::from(batch_start))
+        .bind(Decimal::from(batch_end))
+        .bind(Decimal::from(to_block_number))
+        .bind(to_hash.as_bytes())
+        .execute(pool)
+        .await?;
+        if result.rows_affected() > 0 {
+            log::info!(
+                "Finalized blocks {}..={}, {} rows",
+                batch_start,
+                batch_end,
+                result.rows_affected()
+            );
+        }
+        batch_start = batch_end + 1;
+    }
+    Ok(())
+}
+
+// Returns the number of blocks newly marked as reverted
+async fn revert_blocks_from(
+    pool: &PgPool,
+    from_block_number: u64,
+    from_hash: &H256,
+) -> Result<u64> {
+    let result = sqlx::query(
+        "UPDATE blocks SET finality_status = $1 WHERE number >= $2 AND finality_status <> $1 AND EXISTS (SELECT 1 FROM blocks WHERE number = $2 AND hash = $3)",
+    )
+    .bind(FINALITY_REVERTED)
+    .bind(Decimal::from(from_block_number))
+    .bind(from_hash.as_bytes())
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
@@ -1,15 +1,17 @@
 use std::{
     collections::{HashMap, HashSet},
     iter::FromIterator,
+    sync::Arc,
 };
 
 use crate::{
     helper::{hex, parse_log, GwLog, PolyjuiceArgs, GW_LOG_POLYJUICE_SYSTEM},
-    insert_l2_block::{insert_web3_block, insert_web3_txs_and_logs},
-    pool::POOL,
+    insert_l2_block::UpsertCounts,
+    retry::TransientError,
+    storage::{Storage, SyncStateUpdate},
     types::{
         Block as Web3Block, Log as Web3Log, Transaction as Web3Transaction,
-        TransactionWithLogs as Web3TransactionWithLogs,
+        TransactionWithLogs as Web3TransactionWithLogs, Withdrawal as Web3Withdrawal,
     },
 };
 use anyhow::{anyhow, Result};
@@ -18,17 +20,15 @@ use ckb_types::H256;
 use gw_common::{builtins::CKB_SUDT_ACCOUNT_ID, registry_address::RegistryAddress};
 use gw_types::{
     bytes::Bytes,
-    packed::{L2Block, L2Transaction, SUDTArgs, SUDTArgsUnion, Script, TxReceipt},
+    packed::{
+        L2Block, L2Transaction, RawWithdrawalRequest, SUDTArgs, SUDTArgsUnion, Script, TxReceipt,
+    },
     prelude::Unpack as GwUnpack,
     prelude::*,
     U256,
 };
-use gw_web3_rpc_client::{
-    convertion, godwoken_async_client::GodwokenAsyncClient, godwoken_rpc_client::GodwokenRpcClient,
-};
+use gw_web3_rpc_client::{convertion, godwoken_async_client::GodwokenAsyncClient};
 use itertools::Itertools;
-use rayon::prelude::*;
-use rust_decimal::{prelude::ToPrimitive, Decimal};
 use sqlx::types::chrono::{DateTime, NaiveDateTime, Utc};
 
 const MILLIS_PER_SEC: u64 = 1_000;
@@ -39,8 +39,8 @@ pub struct Web3Indexer {
     polyjuice_type_script_hash: H256,
     rollup_type_hash: H256,
     allowed_eoa_hashes: HashSet<H256>,
-    godwoken_rpc_client: GodwokenRpcClient,
-    godwoken_async_client: GodwokenAsyncClient,
+    godwoken_async_client: Arc<GodwokenAsyncClient>,
+    storage: Arc<dyn Storage>,
 }
 
 impl Web3Indexer {
@@ -49,60 +49,117 @@ impl Web3Indexer {
         polyjuice_type_script_hash: H256,
         rollup_type_hash: H256,
         eth_account_lock_hash: H256,
-        gw_rpc_url: &str,
+        godwoken_async_client: Arc<GodwokenAsyncClient>,
+        storage: Arc<dyn Storage>,
     ) -> Self {
         let mut allowed_eoa_hashes = HashSet::default();
         allowed_eoa_hashes.insert(eth_account_lock_hash);
-        let godwoken_rpc_client = GodwokenRpcClient::new(gw_rpc_url);
-        let godwoken_async_client = GodwokenAsyncClient::with_url(gw_rpc_url).unwrap(); // TODO:
 
         Web3Indexer {
             l2_sudt_type_script_hash,
             polyjuice_type_script_hash,
             rollup_type_hash,
             allowed_eoa_hashes,
-            godwoken_rpc_client,
             godwoken_async_client,
+            storage,
         }
     }
 
-    pub async fn store_l2_block(&self, l2_block: L2Block) -> Result<(usize, usize)> {
+    /// Index a block unless it is already indexed, returns the transaction and log counts.
+    pub async fn store_l2_block(&self, l2_block: L2Block) -> Result<(UpsertCounts, UpsertCounts)> {
         let number: u64 = l2_block.raw().number().unpack();
         let local_tip_number = self.tip_number().await?.unwrap_or(0);
-        let mut txs_len = 0;
-        let mut logs_len = 0;
-        if number > local_tip_number || self.query_number(number).await?.is_none() {
-            (txs_len, logs_len) = self.insert_l2block(l2_block).await?;
+        let mut txs_counts = UpsertCounts::default();
+        let mut logs_counts = UpsertCounts::default();
+        if number > local_tip_number || self.storage.block_hash(number).await?.is_none() {
+            (txs_counts, logs_counts) = self
+                .insert_l2block(l2_block, SyncStateUpdate::Advance)
+                .await?;
             log::debug!(
-                "web3 indexer: sync new block #{}, {} txs, {} logs",
+                "web3 indexer: sync new block #{}, txs: {}, logs: {}",
                 number,
-                txs_len,
-                logs_len
+                txs_counts,
+                logs_counts
             );
         }
-        Ok((txs_len, logs_len))
+        Ok((txs_counts, logs_counts))
     }
 
-    async fn query_number(&self, number: u64) -> Result<Option<u64>> {
-        let row: Option<(Decimal,)> = sqlx::query_as(&format!(
-            "SELECT number FROM blocks WHERE number={} LIMIT 1",
-            number
-        ))
-        .fetch_optional(&*POOL)
-        .await?;
-        Ok(row.and_then(|(n,)| n.to_u64()))
+    /// Upsert the given block over the indexed one at the same number in one db transaction.
+    ///
+    /// Running it again with the same block is a no-op apart from the replaced counts.
+    pub async fn reindex_l2_block(
+        &self,
+        l2_block: L2Block,
+    ) -> Result<(UpsertCounts, UpsertCounts)> {
+        self.insert_l2block(l2_block, SyncStateUpdate::Advance)
+            .await
     }
 
-    async fn tip_number(&self) -> Result<Option<u64>> {
-        let row: Option<(Decimal,)> =
-            sqlx::query_as("SELECT number FROM blocks ORDER BY number DESC LIMIT 1")
-                .fetch_optional(&*POOL)
-                .await?;
-        Ok(row.and_then(|(n,)| n.to_u64()))
+    /// Same as `reindex_l2_block` but the sync state is left to the live indexer, which
+    /// upserts the block again once it gets there.
+    pub async fn backfill_l2_block(
+        &self,
+        l2_block: L2Block,
+    ) -> Result<(UpsertCounts, UpsertCounts)> {
+        self.insert_l2block(l2_block, SyncStateUpdate::Keep).await
+    }
+
+    pub async fn tip_number(&self) -> Result<Option<u64>> {
+        self.storage.tip_number().await
+    }
+
+    /// Hashes of the transactions of the block which are indexed, in block order.
+    pub async fn web3_transaction_hashes(&self, l2_block: &L2Block) -> Result<Vec<[u8; 32]>> {
+        let l2_transactions: Vec<L2Transaction> = l2_block.transactions().into_iter().collect();
+        let id_script_map = self.batch_from_script(&l2_transactions).await?;
+        let mut hashes = vec![];
+        for tx in l2_transactions {
+            if self.is_web3_transaction(&tx, &id_script_map)? {
+                hashes.push(tx.hash());
+            }
+        }
+        Ok(hashes)
+    }
+
+    // Same checks as `filter_single_transaction`, without fetching the receipt
+    fn is_web3_transaction(
+        &self,
+        l2_transaction: &L2Transaction,
+        id_script_map: &HashMap<u32, Option<Script>>,
+    ) -> Result<bool> {
+        let get_script = |id: u32| {
+            id_script_map
+                .get(&id)
+                .and_then(|script| script.as_ref())
+                .ok_or_else(|| anyhow!("Can't get script by id: {:?}", id))
+        };
+
+        let from_id: u32 = l2_transaction.raw().from_id().unpack();
+        let from_script_code_hash: H256 = get_script(from_id)?.code_hash().unpack();
+        if !self.allowed_eoa_hashes.contains(&from_script_code_hash) {
+            return Ok(false);
+        }
+
+        let to_id: u32 = l2_transaction.raw().to_id().unpack();
+        let to_script = get_script(to_id)?;
+        if to_script.code_hash().as_slice() == self.polyjuice_type_script_hash.0 {
+            return Ok(true);
+        }
+        if to_id == CKB_SUDT_ACCOUNT_ID
+            && to_script.code_hash().as_slice() == self.l2_sudt_type_script_hash.0
+        {
+            let sudt_args = SUDTArgs::from_slice(l2_transaction.raw().args().raw_data().as_ref())?;
+            if let SUDTArgsUnion::SUDTTransfer(sudt_transfer) = sudt_args.to_enum() {
+                let to_address = RegistryAddress::from_slice(sudt_transfer.to_address().as_slice());
+                return Ok(matches!(to_address, Some(address) if address.address.len() == 20));
+            }
+        }
+        Ok(false)
     }
 
     // NOTE: remember to update `tx_index`, `cumulative_gas_used`, `log.transaction_index`
-    fn filter_single_transaction(
+    async fn filter_single_transaction(
         &self,
         l2_transaction: L2Transaction,
         block_number: u64,
@@ -203,7 +260,9 @@ impl Web3Indexer {
             let input = polyjuice_args.input.clone().unwrap_or_default();
 
             // read logs
-            let tx_receipt: TxReceipt = self.get_transaction_receipt(gw_tx_hash, block_number)?;
+            let tx_receipt: TxReceipt = self
+                .get_transaction_receipt(gw_tx_hash, block_number)
+                .await?;
             let log_item_vec = tx_receipt.logs();
 
             // read polyjuice system log
@@ -353,8 +412,9 @@ impl Web3Indexer {
 
                     let nonce: u32 = l2_transaction.raw().nonce().unpack();
 
-                    let tx_receipt: TxReceipt =
-                        self.get_transaction_receipt(gw_tx_hash, block_number)?;
+                    let tx_receipt: TxReceipt = self
+                        .get_transaction_receipt(gw_tx_hash, block_number)
+                        .await?;
 
                     let exit_code: u8 = tx_receipt.exit_code().into();
                     let web3_transaction = Web3Transaction::new(
@@ -442,16 +502,18 @@ impl Web3Indexer {
         Ok(hashmap)
     }
 
-    async fn insert_l2block(&self, l2_block: L2Block) -> Result<(usize, usize)> {
-        let block_number = l2_block.raw().number().unpack();
+    // Upsert the block, its transactions and logs, so indexing a block twice converges
+    async fn insert_l2block(
+        &self,
+        l2_block: L2Block,
+        sync_state: SyncStateUpdate,
+    ) -> Result<(UpsertCounts, UpsertCounts)> {
+        let block_number: u64 = l2_block.raw().number().unpack();
         let block_hash: gw_common::H256 = blake2b_256(l2_block.raw().as_slice()).into();
         // let mut cumulative_gas_used: u128 = 0;
         let l2_transactions = l2_block.transactions();
         let l2_transactions_vec: Vec<L2Transaction> = l2_transactions.into_iter().collect();
 
-        let mut logs_len: usize = 0;
-        let mut web3_txs_len: usize = 0;
-
         let id_script_hashmap = self.batch_from_script(&l2_transactions_vec).await?;
 
         let txs_slice = l2_transactions_vec
@@ -461,22 +523,18 @@ impl Web3Indexer {
             .map(|chunk| chunk.collect())
             .collect::<Vec<Vec<_>>>();
 
-        // begin db transaction
-        let pool = &*POOL;
-        let mut pg_tx = pool.begin().await?;
-
+        let mut web3_txs = Vec::new();
         let mut tx_index_cursor: u32 = 0;
         let mut log_index_cursor: u32 = 0;
 
         let mut cumulative_gas_used: u128 = 0;
         let mut total_gas_limit: u128 = 0;
         for txs in txs_slice {
-            let l2_transaction_with_logs_vec = txs
-                .into_par_iter()
-                .map(|tx| {
+            let l2_transaction_with_logs_vec: Vec<Option<Web3TransactionWithLogs>> =
+                futures::future::try_join_all(txs.into_iter().map(|tx| {
                     self.filter_single_transaction(tx, block_number, block_hash, &id_script_hashmap)
-                })
-                .collect::<Result<Vec<Option<Web3TransactionWithLogs>>>>()?;
+                }))
+                .await?;
 
             let txs_vec = l2_transaction_with_logs_vec
                 .into_iter()
@@ -507,28 +565,94 @@ impl Web3Indexer {
                 .collect::<Vec<_>>();
 
             tx_index_cursor += txs_vec.len() as u32;
-
-            // insert to db
-            let (txs_part_len, logs_part_len) =
-                insert_web3_txs_and_logs(txs_vec, &mut pg_tx).await?;
-
-            web3_txs_len += txs_part_len;
-            logs_len += logs_part_len;
+            web3_txs.extend(txs_vec);
         }
 
+        let web3_withdrawals = self
+            .build_withdrawals(&l2_block, block_number, block_hash)
+            .await?;
+
         // insert block
         let web3_block = self
             .build_web3_block(&l2_block, total_gas_limit, cumulative_gas_used)
             .await?;
-        insert_web3_block(web3_block, &mut pg_tx).await?;
+        self.storage
+            .insert_block(web3_block, web3_txs, web3_withdrawals, sync_state)
+            .await
+    }
+
+    // Withdrawal requests of the block, the address is taken from the account script
+    async fn build_withdrawals(
+        &self,
+        l2_block: &L2Block,
+        block_number: u64,
+        block_hash: gw_common::H256,
+    ) -> Result<Vec<Web3Withdrawal>> {
+        let raw_withdrawals: Vec<RawWithdrawalRequest> = l2_block
+            .withdrawals()
+            .into_iter()
+            .map(|withdrawal| withdrawal.raw())
+            .collect();
+        if raw_withdrawals.is_empty() {
+            return Ok(vec![]);
+        }
 
-        // commit
-        pg_tx.commit().await?;
+        let script_hashes: Vec<H256> = raw_withdrawals
+            .iter()
+            .map(|raw| raw.account_script_hash().unpack())
+            .unique()
+            .collect();
+        let scripts = self
+            .godwoken_async_client
+            .get_script_batch(script_hashes.clone())
+            .await?;
+        let addresses: HashMap<H256, Option<[u8; 20]>> = script_hashes
+            .into_iter()
+            .zip(scripts.into_iter())
+            .map(|(script_hash, script)| {
+                let address = script.map(convertion::to_script).and_then(|script| {
+                    let code_hash: H256 = script.code_hash().unpack();
+                    let args = script.args().raw_data();
+                    // Eth account lock args: rollup type hash + eth address
+                    if !self.allowed_eoa_hashes.contains(&code_hash)
+                        || args.len() != 52
+                        || args[0..32] != self.rollup_type_hash.0
+                    {
+                        return None;
+                    }
+                    let mut address = [0u8; 20];
+                    address.copy_from_slice(&args[32..52]);
+                    Some(address)
+                });
+                (script_hash, address)
+            })
+            .collect();
 
-        Ok((web3_txs_len, logs_len))
+        let withdrawals = raw_withdrawals
+            .into_iter()
+            .enumerate()
+            .map(|(withdrawal_index, raw)| {
+                let account_script_hash: H256 = raw.account_script_hash().unpack();
+                let address = addresses.get(&account_script_hash).copied().flatten();
+                Web3Withdrawal {
+                    block_number,
+                    block_hash,
+                    withdrawal_index: withdrawal_index as u32,
+                    account_script_hash: raw.account_script_hash().unpack(),
+                    registry_id: raw.registry_id().unpack(),
+                    address,
+                    amount: raw.amount().unpack(),
+                    capacity: raw.capacity().unpack(),
+                    udt_script_hash: raw.sudt_script_hash().unpack(),
+                    owner_lock_hash: raw.owner_lock_hash().unpack(),
+                    nonce: raw.nonce().unpack(),
+                }
+            })
+            .collect();
+        Ok(withdrawals)
     }
 
-    fn get_transaction_receipt(
+    async fn get_transaction_receipt(
         &self,
         gw_tx_hash: gw_common::H256,
         block_number: u64,
@@ -537,37 +661,19 @@ impl Web3Indexer {
         let tx_hash_hex = hex(tx_hash.as_bytes())
             .unwrap_or_else(|_| format!("convert tx hash: {:?} to hex format failed", tx_hash));
 
-        let get_receipt = || -> Result<TxReceipt> {
-            let tx_receipt: TxReceipt = self
-                .godwoken_rpc_client
-                .get_transaction_receipt(&tx_hash)?
-                .ok_or_else(|| {
-                    anyhow!(
-                        "tx receipt not found by tx_hash: ({}) of block: {}",
-                        tx_hash_hex,
-                        block_number,
-                    )
-                })?
-                .into();
-            Ok(tx_receipt)
-        };
-
-        let max_retry = 10;
-        let mut retry_times = 0;
-        while retry_times < max_retry {
-            let receipt = get_receipt();
-            match receipt {
-                Ok(tx_receipt) => return Ok(tx_receipt),
-                Err(err) => {
-                    log::error!("{}", err);
-                    retry_times += 1;
-                    // sleep and retry
-                    let sleep_time = std::time::Duration::from_secs(retry_times);
-                    std::thread::sleep(sleep_time);
-                }
-            }
-        }
-        get_receipt()
+        // Retried with the whole block by the caller
+        let tx_receipt = self
+            .godwoken_async_client
+            .get_transaction_receipt(&tx_hash)
+            .await?
+            .ok_or_else(|| {
+                // The node may not serve the receipt yet
+                anyhow::Error::from(TransientError(format!(
+                    "tx receipt not found by tx_hash: ({}) of block: {}",
+                    tx_hash_hex, block_number,
+                )))
+            })?;
+        Ok(tx_receipt.into())
     }
 
     async fn build_web3_block(
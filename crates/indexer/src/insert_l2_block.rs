@@ -1,8 +1,9 @@
-use std::{convert::TryFrom, str::FromStr};
+use std::{collections::HashMap, convert::TryFrom, fmt, ops::AddAssign, str::FromStr};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use gw_types::U256;
-use rust_decimal::Decimal;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::{Deserialize, Serialize};
 use sqlx::{
     postgres::PgRow,
     types::{
@@ -13,12 +14,48 @@ use sqlx::{
 };
 use sqlx::{Postgres, QueryBuilder};
 
-use crate::types::{Block, Log, Transaction, TransactionWithLogs};
+use crate::types::{Block, Log, Transaction, TransactionWithLogs, Withdrawal};
 
 use itertools::Itertools;
 use rayon::prelude::*;
 
 const INSERT_LOGS_BATCH_SIZE: usize = 5000;
+// 11 bind parameters each
+const INSERT_WITHDRAWALS_BATCH_SIZE: usize = 1000;
+
+/// Rows written by an upsert, split into inserted rows and replaced existing ones.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UpsertCounts {
+    pub new: usize,
+    pub replaced: usize,
+}
+
+impl UpsertCounts {
+    pub fn total(&self) -> usize {
+        self.new + self.replaced
+    }
+
+    pub(crate) fn count(&mut self, inserted: bool) {
+        if inserted {
+            self.new += 1;
+        } else {
+            self.replaced += 1;
+        }
+    }
+}
+
+impl AddAssign for UpsertCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.new += other.new;
+        self.replaced += other.replaced;
+    }
+}
+
+impl fmt::Display for UpsertCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} new, {} replaced", self.new, self.replaced)
+    }
+}
 
 pub struct DbBlock<'a> {
     number: Decimal,
@@ -105,7 +142,6 @@ impl TryFrom<Transaction> for DbTransaction {
 
 #[derive(Debug, Clone)]
 pub struct DbLog {
-    transaction_id: i64,
     transaction_hash: Vec<u8>,
     transaction_index: Decimal,
     block_number: Decimal,
@@ -117,7 +153,7 @@ pub struct DbLog {
 }
 
 impl DbLog {
-    pub fn try_from_log(log: Log, transaction_id: i64) -> Result<DbLog> {
+    pub fn try_from_log(log: Log) -> Result<DbLog> {
         let topics = log
             .topics
             .into_iter()
@@ -125,7 +161,6 @@ impl DbLog {
             .collect();
 
         let db_log = Self {
-            transaction_id,
             transaction_hash: log.transaction_hash.as_slice().to_vec(),
             transaction_index: log.transaction_index.into(),
             block_number: log.block_number.into(),
@@ -139,14 +174,54 @@ impl DbLog {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct DbWithdrawal {
+    block_number: Decimal,
+    block_hash: Vec<u8>,
+    withdrawal_index: Decimal,
+    account_script_hash: Vec<u8>,
+    registry_id: Decimal,
+    address: Option<Vec<u8>>,
+    amount: BigDecimal,
+    capacity: Decimal,
+    udt_script_hash: Vec<u8>,
+    owner_lock_hash: Vec<u8>,
+    nonce: Decimal,
+}
+
+impl TryFrom<Withdrawal> for DbWithdrawal {
+    type Error = anyhow::Error;
+
+    fn try_from(w: Withdrawal) -> Result<DbWithdrawal, Self::Error> {
+        let db_withdrawal = Self {
+            block_number: w.block_number.into(),
+            block_hash: w.block_hash.as_slice().to_vec(),
+            withdrawal_index: w.withdrawal_index.into(),
+            account_script_hash: w.account_script_hash.as_slice().to_vec(),
+            registry_id: w.registry_id.into(),
+            address: w.address.map(|addr| addr.to_vec()),
+            amount: u128_to_big_decimal(&w.amount)?,
+            capacity: w.capacity.into(),
+            udt_script_hash: w.udt_script_hash.as_slice().to_vec(),
+            owner_lock_hash: w.owner_lock_hash.as_slice().to_vec(),
+            nonce: w.nonce.into(),
+        };
+        Ok(db_withdrawal)
+    }
+}
+
+// Upsert by number. A block replaced by another hash is unfinalized again.
 pub async fn insert_web3_block(
     web3_block: Block,
     pg_tx: &mut sqlx::Transaction<'_, Postgres>,
-) -> Result<()> {
+) -> Result<UpsertCounts> {
     let block = DbBlock::try_from(&web3_block)?;
 
-    sqlx::query(
-        "INSERT INTO blocks (number, hash, parent_hash, gas_limit, gas_used, timestamp, miner, size) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"
+    let (inserted,): (bool,) = sqlx::query_as(
+        "INSERT INTO blocks (number, hash, parent_hash, gas_limit, gas_used, timestamp, miner, size) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT (number) DO UPDATE SET hash = EXCLUDED.hash, parent_hash = EXCLUDED.parent_hash, gas_limit = EXCLUDED.gas_limit, gas_used = EXCLUDED.gas_used, timestamp = EXCLUDED.timestamp, miner = EXCLUDED.miner, size = EXCLUDED.size,
+        finality_status = CASE WHEN blocks.hash = EXCLUDED.hash THEN blocks.finality_status ELSE 'unfinalized' END
+        RETURNING (xmax = 0)"
     )
         .bind(block.number)
         .bind(block.hash)
@@ -156,29 +231,80 @@ pub async fn insert_web3_block(
         .bind(block.timestamp)
         .bind(block.miner)
         .bind(block.size)
-        .execute(pg_tx)
+        .fetch_one(pg_tx)
         .await?;
 
-    Ok(())
+    let mut counts = UpsertCounts::default();
+    counts.count(inserted);
+    Ok(counts)
+}
+
+/// How transactions and logs are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InsertMode {
+    /// Multi-row `INSERT ... VALUES`, logs are written in chunks of
+    /// `INSERT_LOGS_BATCH_SIZE` to stay below the bind parameter limit.
+    Values,
+    /// One `INSERT ... SELECT FROM UNNEST` per table with a bind parameter per column,
+    /// whatever the number of rows. Faster for blocks with many events, e.g. when
+    /// catching up.
+    Unnest,
+}
+
+impl Default for InsertMode {
+    fn default() -> Self {
+        InsertMode::Values
+    }
+}
+
+impl fmt::Display for InsertMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InsertMode::Values => write!(f, "values"),
+            InsertMode::Unnest => write!(f, "unnest"),
+        }
+    }
+}
+
+impl FromStr for InsertMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "values" => Ok(InsertMode::Values),
+            "unnest" => Ok(InsertMode::Unnest),
+            _ => Err(anyhow!(
+                "unknown insert mode {:?}, expected values or unnest",
+                s
+            )),
+        }
+    }
 }
 
+const TXS_COLUMNS: &str = "hash, eth_tx_hash, block_number, block_hash, transaction_index, from_address, to_address, value, nonce, gas_limit, gas_price, input, v, r, s, cumulative_gas_used, gas_used, contract_address, exit_code";
+const TXS_ON_CONFLICT: &str = " ON CONFLICT (block_number, transaction_index) DO UPDATE SET hash = EXCLUDED.hash, eth_tx_hash = EXCLUDED.eth_tx_hash, block_hash = EXCLUDED.block_hash, from_address = EXCLUDED.from_address, to_address = EXCLUDED.to_address, value = EXCLUDED.value, nonce = EXCLUDED.nonce, gas_limit = EXCLUDED.gas_limit, gas_price = EXCLUDED.gas_price, input = EXCLUDED.input, v = EXCLUDED.v, r = EXCLUDED.r, s = EXCLUDED.s, cumulative_gas_used = EXCLUDED.cumulative_gas_used, gas_used = EXCLUDED.gas_used, contract_address = EXCLUDED.contract_address, exit_code = EXCLUDED.exit_code RETURNING id, transaction_index, (xmax = 0) AS inserted";
+const LOGS_COLUMNS: &str = "transaction_id, transaction_hash, transaction_index, block_number, block_hash, address, data, log_index, topics";
+const LOGS_ON_CONFLICT: &str = " ON CONFLICT (transaction_id, log_index) DO UPDATE SET transaction_hash = EXCLUDED.transaction_hash, transaction_index = EXCLUDED.transaction_index, block_number = EXCLUDED.block_number, block_hash = EXCLUDED.block_hash, address = EXCLUDED.address, data = EXCLUDED.data, topics = EXCLUDED.topics RETURNING transaction_id, log_index, (xmax = 0) AS inserted";
+
+/// Upsert transactions by `(block_number, transaction_index)` and their logs by
+/// `(transaction_id, log_index)`. Returns the counts and the keys of the written logs.
 pub async fn insert_web3_txs_and_logs(
     web3_tx_with_logs_vec: Vec<TransactionWithLogs>,
+    insert_mode: InsertMode,
     pg_tx: &mut sqlx::Transaction<'_, Postgres>,
-) -> Result<(usize, usize)> {
+) -> Result<(UpsertCounts, UpsertCounts, Vec<(i64, i32)>)> {
     if web3_tx_with_logs_vec.is_empty() {
-        return Ok((0, 0));
+        return Ok((UpsertCounts::default(), UpsertCounts::default(), vec![]));
     }
 
     let (txs, logs) = web3_tx_with_logs_vec
         .into_par_iter()
-        .enumerate()
-        .map(|(i, web3_tx_with_logs)| {
-            // Set transaction_id to txs' index
+        .map(|web3_tx_with_logs| {
             let db_logs: Result<Vec<DbLog>> = web3_tx_with_logs
                 .logs
                 .into_par_iter()
-                .map(|l| DbLog::try_from_log(l, i as i64))
+                .map(DbLog::try_from_log)
                 .collect();
             (DbTransaction::try_from(web3_tx_with_logs.tx), db_logs)
         })
@@ -187,20 +313,58 @@ pub async fn insert_web3_txs_and_logs(
     let logs = logs.into_iter().collect::<Result<Vec<_>>>()?;
     let logs = logs.into_iter().flatten().collect::<Vec<_>>();
 
-    let logs_len = logs.len();
-    let txs_len = txs.len();
+    let tx_rows = match insert_mode {
+        InsertMode::Values => upsert_txs_values(txs, pg_tx).await?,
+        InsertMode::Unnest => upsert_txs_unnest(txs, pg_tx).await?,
+    };
 
-    let logs_slice = logs
-        .into_iter()
-        .chunks(INSERT_LOGS_BATCH_SIZE)
+    // Ids of existing rows are kept, so map them by transaction_index
+    let mut txs_counts = UpsertCounts::default();
+    let mut tx_ids: HashMap<i32, i64> = HashMap::with_capacity(tx_rows.len());
+    for row in tx_rows.iter() {
+        tx_ids.insert(row.get("transaction_index"), row.get("id"));
+        txs_counts.count(row.get("inserted"));
+    }
+    let logs = logs
         .into_iter()
-        .map(|chunk| chunk.collect())
-        .collect::<Vec<Vec<_>>>();
+        .map(|log| {
+            let transaction_id = log
+                .transaction_index
+                .to_i32()
+                .and_then(|index| tx_ids.get(&index))
+                .copied()
+                .ok_or_else(|| {
+                    anyhow!(
+                        "transaction {} of log {} not inserted",
+                        log.transaction_index,
+                        log.log_index
+                    )
+                })?;
+            Ok((transaction_id, log))
+        })
+        .collect::<Result<Vec<_>>>()?;
 
-    let mut txs_query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
-                "INSERT INTO transactions
-                (hash, eth_tx_hash, block_number, block_hash, transaction_index, from_address, to_address, value, nonce, gas_limit, gas_price, input, v, r, s, cumulative_gas_used, gas_used, contract_address, exit_code) "
-            );
+    let log_rows = match insert_mode {
+        InsertMode::Values => upsert_logs_values(logs, pg_tx).await?,
+        InsertMode::Unnest => upsert_logs_unnest(logs, pg_tx).await?,
+    };
+
+    let mut logs_counts = UpsertCounts::default();
+    let mut log_keys = Vec::with_capacity(log_rows.len());
+    for row in log_rows.iter() {
+        log_keys.push((row.get("transaction_id"), row.get("log_index")));
+        logs_counts.count(row.get("inserted"));
+    }
+
+    Ok((txs_counts, logs_counts, log_keys))
+}
+
+async fn upsert_txs_values(
+    txs: Vec<DbTransaction>,
+    pg_tx: &mut sqlx::Transaction<'_, Postgres>,
+) -> Result<Vec<PgRow>> {
+    let mut txs_query_builder: QueryBuilder<Postgres> =
+        QueryBuilder::new(format!("INSERT INTO transactions ({}) ", TXS_COLUMNS));
 
     txs_query_builder
         .push_values(txs, |mut b, tx| {
@@ -224,52 +388,255 @@ pub async fn insert_web3_txs_and_logs(
                 .push_bind(tx.contract_address)
                 .push_bind(tx.exit_code);
         })
-        .push(" RETURNING id");
+        .push(TXS_ON_CONFLICT);
 
-    let mut tx_ids: Vec<i64> = vec![];
+    let rows = txs_query_builder.build().fetch_all(pg_tx).await?;
+    Ok(rows)
+}
 
-    let query = txs_query_builder.build();
-    let rows: Vec<PgRow> = query.fetch_all(&mut (*pg_tx)).await?;
-    let mut ids = rows
-        .iter()
-        .map(|r| r.get::<i64, _>("id"))
-        .collect::<Vec<i64>>();
-    tx_ids.append(&mut ids);
+async fn upsert_txs_unnest(
+    txs: Vec<DbTransaction>,
+    pg_tx: &mut sqlx::Transaction<'_, Postgres>,
+) -> Result<Vec<PgRow>> {
+    let len = txs.len();
+    let mut hash = Vec::with_capacity(len);
+    let mut eth_tx_hash = Vec::with_capacity(len);
+    let mut block_number = Vec::with_capacity(len);
+    let mut block_hash = Vec::with_capacity(len);
+    let mut transaction_index = Vec::with_capacity(len);
+    let mut from_address = Vec::with_capacity(len);
+    let mut to_address = Vec::with_capacity(len);
+    let mut value = Vec::with_capacity(len);
+    let mut nonce = Vec::with_capacity(len);
+    let mut gas_limit = Vec::with_capacity(len);
+    let mut gas_price = Vec::with_capacity(len);
+    let mut input = Vec::with_capacity(len);
+    let mut v = Vec::with_capacity(len);
+    let mut r = Vec::with_capacity(len);
+    let mut s = Vec::with_capacity(len);
+    let mut cumulative_gas_used = Vec::with_capacity(len);
+    let mut gas_used = Vec::with_capacity(len);
+    let mut contract_address = Vec::with_capacity(len);
+    let mut exit_code = Vec::with_capacity(len);
+    for tx in txs {
+        hash.push(tx.hash);
+        eth_tx_hash.push(tx.eth_tx_hash);
+        block_number.push(tx.block_number);
+        block_hash.push(tx.block_hash);
+        transaction_index.push(tx.transaction_index);
+        from_address.push(tx.from_address);
+        to_address.push(tx.to_address);
+        value.push(tx.value);
+        nonce.push(tx.nonce);
+        gas_limit.push(tx.gas_limit);
+        gas_price.push(tx.gas_price);
+        input.push(tx.input);
+        v.push(tx.v);
+        r.push(tx.r);
+        s.push(tx.s);
+        cumulative_gas_used.push(tx.cumulative_gas_used);
+        gas_used.push(tx.gas_used);
+        contract_address.push(tx.contract_address);
+        exit_code.push(tx.exit_code);
+    }
+
+    let sql = format!(
+        "INSERT INTO transactions ({}) SELECT * FROM UNNEST($1::bytea[], $2::bytea[], $3::numeric[], $4::bytea[], $5::numeric[], $6::bytea[], $7::bytea[], $8::numeric[], $9::numeric[], $10::numeric[], $11::numeric[], $12::bytea[], $13::numeric[], $14::bytea[], $15::bytea[], $16::numeric[], $17::numeric[], $18::bytea[], $19::numeric[]){}",
+        TXS_COLUMNS, TXS_ON_CONFLICT
+    );
+    let rows = sqlx::query(&sql)
+        .bind(hash)
+        .bind(eth_tx_hash)
+        .bind(block_number)
+        .bind(block_hash)
+        .bind(transaction_index)
+        .bind(from_address)
+        .bind(to_address)
+        .bind(value)
+        .bind(nonce)
+        .bind(gas_limit)
+        .bind(gas_price)
+        .bind(input)
+        .bind(v)
+        .bind(r)
+        .bind(s)
+        .bind(cumulative_gas_used)
+        .bind(gas_used)
+        .bind(contract_address)
+        .bind(exit_code)
+        .fetch_all(pg_tx)
+        .await?;
+    Ok(rows)
+}
+
+async fn upsert_logs_values(
+    logs: Vec<(i64, DbLog)>,
+    pg_tx: &mut sqlx::Transaction<'_, Postgres>,
+) -> Result<Vec<PgRow>> {
+    let logs_slice = logs
+        .into_iter()
+        .chunks(INSERT_LOGS_BATCH_SIZE)
+        .into_iter()
+        .map(|chunk| chunk.collect())
+        .collect::<Vec<Vec<_>>>();
 
     let logs_querys = logs_slice
-            .into_par_iter()
-            .map(|db_logs| {
-                let mut logs_query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
-                    "INSERT INTO logs
-                    (transaction_id, transaction_hash, transaction_index, block_number, block_hash, address, data, log_index, topics)"
-                );
-
-                // Get transaction id from preview insert returning
-                logs_query_builder.push_values(db_logs, |mut b, log| {
-                    // transaction_id in log is transaction_id_index now
-                    let transaction_id = tx_ids[log.transaction_id as usize];
-
-                    b.push_bind(transaction_id)
-                        .push_bind(log.transaction_hash)
-                        .push_bind(log.transaction_index)
-                        .push_bind(log.block_number)
-                        .push_bind(log.block_hash)
-                        .push_bind(log.address)
-                        .push_bind(log.data)
-                        .push_bind(log.log_index)
-                        .push_bind(log.topics);
-                });
-                logs_query_builder
-            }).collect::<Vec<_>>();
-
-    if logs_len != 0 {
-        for mut query_builder in logs_querys {
-            let query = query_builder.build();
-            query.execute(&mut (*pg_tx)).await?;
-        }
+        .into_par_iter()
+        .map(|db_logs| {
+            let mut logs_query_builder: QueryBuilder<Postgres> =
+                QueryBuilder::new(format!("INSERT INTO logs ({}) ", LOGS_COLUMNS));
+            logs_query_builder.push_values(db_logs, |mut b, (transaction_id, log)| {
+                b.push_bind(transaction_id)
+                    .push_bind(log.transaction_hash)
+                    .push_bind(log.transaction_index)
+                    .push_bind(log.block_number)
+                    .push_bind(log.block_hash)
+                    .push_bind(log.address)
+                    .push_bind(log.data)
+                    .push_bind(log.log_index)
+                    .push_bind(log.topics);
+            });
+            logs_query_builder.push(LOGS_ON_CONFLICT);
+            logs_query_builder
+        })
+        .collect::<Vec<_>>();
+
+    let mut rows = vec![];
+    for mut query_builder in logs_querys {
+        let mut part_rows = query_builder.build().fetch_all(&mut (*pg_tx)).await?;
+        rows.append(&mut part_rows);
     }
+    Ok(rows)
+}
+
+// bytea[][] must be rectangular, so each log's topics are bound concatenated and split
+// into 32 bytes topics again in SQL
+const SPLIT_TOPICS: &str = "ARRAY(SELECT substring(t.topics FROM i FOR 32) FROM generate_series(1, length(t.topics), 32) AS i ORDER BY i)";
 
-    Ok((txs_len, logs_len))
+async fn upsert_logs_unnest(
+    logs: Vec<(i64, DbLog)>,
+    pg_tx: &mut sqlx::Transaction<'_, Postgres>,
+) -> Result<Vec<PgRow>> {
+    if logs.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let len = logs.len();
+    let mut transaction_id = Vec::with_capacity(len);
+    let mut transaction_hash = Vec::with_capacity(len);
+    let mut transaction_index = Vec::with_capacity(len);
+    let mut block_number = Vec::with_capacity(len);
+    let mut block_hash = Vec::with_capacity(len);
+    let mut address = Vec::with_capacity(len);
+    let mut data = Vec::with_capacity(len);
+    let mut log_index = Vec::with_capacity(len);
+    let mut topics = Vec::with_capacity(len);
+    for (id, log) in logs {
+        transaction_id.push(id);
+        transaction_hash.push(log.transaction_hash);
+        transaction_index.push(log.transaction_index);
+        block_number.push(log.block_number);
+        block_hash.push(log.block_hash);
+        address.push(log.address);
+        data.push(log.data);
+        log_index.push(log.log_index);
+        topics.push(log.topics.concat());
+    }
+
+    let sql = format!(
+        "INSERT INTO logs ({}) SELECT t.transaction_id, t.transaction_hash, t.transaction_index, t.block_number, t.block_hash, t.address, t.data, t.log_index, {} FROM UNNEST($1::bigint[], $2::bytea[], $3::numeric[], $4::numeric[], $5::bytea[], $6::bytea[], $7::bytea[], $8::numeric[], $9::bytea[]) AS t({}){}",
+        LOGS_COLUMNS, SPLIT_TOPICS, LOGS_COLUMNS, LOGS_ON_CONFLICT
+    );
+    let rows = sqlx::query(&sql)
+        .bind(transaction_id)
+        .bind(transaction_hash)
+        .bind(transaction_index)
+        .bind(block_number)
+        .bind(block_hash)
+        .bind(address)
+        .bind(data)
+        .bind(log_index)
+        .bind(topics)
+        .fetch_all(pg_tx)
+        .await?;
+    Ok(rows)
+}
+
+/// Remove rows of a re-indexed block which are not part of it anymore, returns the
+/// number of removed transactions and logs.
+pub async fn remove_surplus_rows(
+    block_number: u64,
+    txs_count: usize,
+    log_keys: Vec<(i64, i32)>,
+    pg_tx: &mut sqlx::Transaction<'_, Postgres>,
+) -> Result<(u64, u64)> {
+    let number = Decimal::from(block_number);
+    let (transaction_ids, log_indexes): (Vec<i64>, Vec<i32>) = log_keys.into_iter().unzip();
+    let logs = sqlx::query(
+        "DELETE FROM logs WHERE block_number = $1 AND (transaction_id, log_index) NOT IN (SELECT * FROM UNNEST($2::bigint[], $3::integer[]))",
+    )
+    .bind(number)
+    .bind(transaction_ids)
+    .bind(log_indexes)
+    .execute(&mut (*pg_tx))
+    .await?
+    .rows_affected();
+    let txs_count = i32::try_from(txs_count).map_err(|_| anyhow!("too many transactions"))?;
+    let txs =
+        sqlx::query("DELETE FROM transactions WHERE block_number = $1 AND transaction_index >= $2")
+            .bind(number)
+            .bind(txs_count)
+            .execute(&mut (*pg_tx))
+            .await?
+            .rows_affected();
+    Ok((txs, logs))
+}
+
+/// Replace the withdrawals of a block, returns the number of inserted rows.
+pub async fn insert_web3_withdrawals(
+    block_number: u64,
+    withdrawals: Vec<Withdrawal>,
+    pg_tx: &mut sqlx::Transaction<'_, Postgres>,
+) -> Result<u64> {
+    sqlx::query("DELETE FROM withdrawals WHERE block_number = $1")
+        .bind(Decimal::from(block_number))
+        .execute(&mut (*pg_tx))
+        .await?;
+
+    let withdrawals_slice = withdrawals
+        .into_iter()
+        .map(DbWithdrawal::try_from)
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .chunks(INSERT_WITHDRAWALS_BATCH_SIZE)
+        .into_iter()
+        .map(|chunk| chunk.collect())
+        .collect::<Vec<Vec<_>>>();
+    let mut inserted = 0;
+    for withdrawals in withdrawals_slice {
+        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO withdrawals (block_number, block_hash, withdrawal_index, account_script_hash, registry_id, address, amount, capacity, udt_script_hash, owner_lock_hash, nonce) ",
+        );
+        query_builder.push_values(withdrawals, |mut b, w| {
+            b.push_bind(w.block_number)
+                .push_bind(w.block_hash)
+                .push_bind(w.withdrawal_index)
+                .push_bind(w.account_script_hash)
+                .push_bind(w.registry_id)
+                .push_bind(w.address)
+                .push_bind(w.amount)
+                .push_bind(w.capacity)
+                .push_bind(w.udt_script_hash)
+                .push_bind(w.owner_lock_hash)
+                .push_bind(w.nonce);
+        });
+        inserted += query_builder
+            .build()
+            .execute(&mut (*pg_tx))
+            .await?
+            .rows_affected();
+    }
+    Ok(inserted)
 }
 
 fn u128_to_big_decimal(value: &u128) -> Result<BigDecimal> {
@@ -281,3 +648,46 @@ fn u256_to_big_decimal(value: &U256) -> Result<BigDecimal> {
     let result = BigDecimal::from_str(&value.to_string())?;
     Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use sqlx::{Connection, PgConnection};
+
+    use super::*;
+
+    // Runs against the database at PG_URL, skipped when it is unset
+    #[test]
+    fn split_unnest_topics() {
+        let pg_url = match env::var("PG_URL") {
+            Ok(pg_url) => pg_url,
+            Err(_) => {
+                eprintln!("PG_URL is unset, skip split_unnest_topics");
+                return;
+            }
+        };
+        let four_topics: Vec<Vec<u8>> = (1..=4u8).map(|i| vec![i; 32]).collect();
+        let concatenated = vec![vec![], four_topics.concat()];
+
+        let split: Vec<Vec<Vec<u8>>> = smol::block_on(async {
+            let mut conn = PgConnection::connect(&pg_url).await?;
+            let sql = format!(
+                "SELECT {} FROM UNNEST($1::bytea[]) WITH ORDINALITY AS t(topics, n) ORDER BY n",
+                SPLIT_TOPICS
+            );
+            let rows = sqlx::query(&sql)
+                .bind(concatenated)
+                .fetch_all(&mut conn)
+                .await?;
+            let split = rows
+                .iter()
+                .map(|row| row.try_get(0))
+                .collect::<Result<_, _>>()?;
+            Ok::<_, anyhow::Error>(split)
+        })
+        .unwrap();
+
+        assert_eq!(split, vec![vec![], four_topics]);
+    }
+}
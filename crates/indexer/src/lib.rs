@@ -1,9 +1,26 @@
 pub mod config;
+pub mod finality;
 pub mod helper;
 pub mod indexer;
 pub mod insert_l2_block;
+pub mod lock;
+pub mod migration;
 pub mod pool;
+pub mod prefetch;
+pub mod retry;
 pub mod runner;
+pub mod secret;
+pub mod storage;
 pub mod types;
+pub mod verifier;
 
 pub use indexer::Web3Indexer;
+
+pub const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("GIT_COMMIT_HASH"),
+    " ",
+    env!("GIT_COMMIT_DATE"),
+    ")"
+);
@@ -0,0 +1,154 @@
+use std::{
+    convert::TryInto,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use ckb_types::H256;
+use sqlx::{PgConnection, PgPool};
+
+use crate::storage::{require_pg_pool, Storage};
+
+/// Session level Postgres advisory lock, only the holder is allowed to write blocks.
+///
+/// The lock lives on a dedicated connection which is detached from the pool, so it is
+/// released by Postgres as soon as the holder exits or loses its connection. Writes go
+/// through pooled connections and are fenced by [`WriterFence`].
+pub struct WriterLock {
+    // Kept open to hold the lock
+    _conn: PgConnection,
+    fence: WriterFence,
+}
+
+/// Fencing token issued with a writer lock, every acquisition bumps the token in the
+/// `writer_fence` row. A write transaction updates the row only if it still has its own
+/// token, so a writer which lost the lock, e.g. its lock connection was dropped and another
+/// indexer took over, can't commit anymore.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriterFence {
+    token: i64,
+}
+
+impl WriterFence {
+    /// Errors if another writer took over, losing the lock is fatal.
+    ///
+    /// The row stays locked until the transaction ends, so a takeover waits for the
+    /// transaction to finish and the check holds until commit.
+    pub async fn check(&self, conn: &mut PgConnection) -> Result<()> {
+        let updated =
+            sqlx::query("UPDATE writer_fence SET updated_at = now() WHERE id = 1 AND token = $1")
+                .bind(self.token)
+                .execute(conn)
+                .await?
+                .rows_affected();
+        if updated == 0 {
+            log::error!("Lost writer lock, fencing token {} is stale", self.token);
+            return Err(anyhow!(
+                "lost writer lock, fencing token {} is stale",
+                self.token
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl WriterLock {
+    /// Wait until the lock keyed by `rollup_type_hash` is acquired, returns None if
+    /// `shutdown` is set while waiting.
+    pub async fn acquire(
+        pool: &PgPool,
+        rollup_type_hash: &H256,
+        retry_interval: Duration,
+        shutdown: &AtomicBool,
+    ) -> Result<Option<WriterLock>> {
+        loop {
+            if let Some(lock) = Self::try_acquire(pool, rollup_type_hash).await? {
+                return Ok(Some(lock));
+            }
+            if shutdown.load(Ordering::SeqCst) {
+                return Ok(None);
+            }
+
+            log::info!(
+                "Writer lock {} is held by another indexer, retry in {:?}",
+                lock_key(rollup_type_hash)?,
+                retry_interval
+            );
+            smol::Timer::after(retry_interval).await;
+        }
+    }
+
+    /// Acquire the lock keyed by `rollup_type_hash` without waiting, returns None if another
+    /// process holds it.
+    pub async fn try_acquire(pool: &PgPool, rollup_type_hash: &H256) -> Result<Option<WriterLock>> {
+        let key = lock_key(rollup_type_hash)?;
+        let mut conn = pool.acquire().await?.detach();
+        let (locked,): (bool,) = sqlx::query_as("SELECT pg_try_advisory_lock($1)")
+            .bind(key)
+            .fetch_one(&mut conn)
+            .await?;
+        if !locked {
+            return Ok(None);
+        }
+        // Waits for write transactions of the previous holder still in flight
+        let (token,): (i64,) = sqlx::query_as(
+            "INSERT INTO writer_fence (id, token, updated_at) VALUES (1, 1, now())
+            ON CONFLICT (id) DO UPDATE SET token = writer_fence.token + 1, updated_at = EXCLUDED.updated_at
+            RETURNING token",
+        )
+        .fetch_one(&mut conn)
+        .await?;
+        log::info!("Acquired writer lock {}, fencing token {}", key, token);
+        Ok(Some(WriterLock {
+            _conn: conn,
+            fence: WriterFence { token },
+        }))
+    }
+
+    pub fn fence(&self) -> WriterFence {
+        self.fence
+    }
+}
+
+fn lock_key(rollup_type_hash: &H256) -> Result<i64> {
+    Ok(i64::from_be_bytes(
+        rollup_type_hash.as_bytes()[0..8].try_into()?,
+    ))
+}
+
+/// Wait until this process is the only writer of `storage`, whose writes are fenced by the
+/// returned lock. Returns None if `shutdown` is set while waiting.
+pub async fn acquire_writer(
+    storage: &dyn Storage,
+    rollup_type_hash: &H256,
+    retry_interval: Duration,
+    shutdown: &AtomicBool,
+) -> Result<Option<WriterLock>> {
+    let pool = require_pg_pool(storage, "writer lock")?;
+    let lock = WriterLock::acquire(pool, rollup_type_hash, retry_interval, shutdown).await?;
+    if let Some(lock) = &lock {
+        storage.set_writer_fence(Some(lock.fence()));
+    }
+    Ok(lock)
+}
+
+/// Become the only writer of `storage` for the one-off `command`, fails right away if
+/// another indexer is the writer instead of waiting behind it.
+pub async fn try_acquire_writer(
+    storage: &dyn Storage,
+    rollup_type_hash: &H256,
+    command: &str,
+) -> Result<WriterLock> {
+    let pool = require_pg_pool(storage, "writer lock")?;
+    let lock = WriterLock::try_acquire(pool, rollup_type_hash)
+        .await?
+        .ok_or_else(|| {
+            anyhow!(
+                "writer lock is held by another indexer, stop it before {}",
+                command
+            )
+        })?;
+    storage.set_writer_fence(Some(lock.fence()));
+    Ok(lock)
+}
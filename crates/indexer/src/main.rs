@@ -1,16 +1,83 @@
-use gw_web3_indexer::{config::load_indexer_config, runner::Runner};
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
-use anyhow::Result;
+use gw_web3_indexer::{
+    config::{load_indexer_config, resolve_chain_spec},
+    migration::migrate,
+    pool::build_pool,
+    runner::Runner,
+    storage::open_storage,
+    verifier::Verifier,
+    VERSION,
+};
+
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
 use sentry_log::LogFilter;
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+#[derive(Parser)]
+#[clap(name = "gw-web3-indexer", version = VERSION, about = "Godwoken web3 indexer")]
+struct Cli {
+    /// Path to the indexer config file
+    #[clap(long, global = true, default_value = "./indexer-config.toml")]
+    config: PathBuf,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Follow the chain and index new blocks (default)
+    Run,
+    /// Re-index blocks in [from, to] and exit, defaults to `backfill_from` and `backfill_to` in
+    /// config. Runs next to the live indexer
+    Backfill {
+        #[clap(long)]
+        from: Option<u64>,
+        #[clap(long)]
+        to: Option<u64>,
+    },
+    /// Move every indexed block above the given block number to the orphan tables, refused
+    /// while another indexer holds the writer lock
+    Rollback {
+        #[clap(long)]
+        to: u64,
+    },
+    /// Compare indexed blocks in [from, to] with the chain, `to` defaults to the local tip
+    Verify {
+        #[clap(long, default_value = "0")]
+        from: u64,
+        #[clap(long)]
+        to: Option<u64>,
+        /// Re-index missing and mismatched blocks, refused while another indexer holds the writer lock
+        #[clap(long)]
+        repair: bool,
+        /// Keep verifying new blocks after reaching the local tip
+        #[clap(long)]
+        follow: bool,
+        /// Seconds between two rounds in follow mode
+        #[clap(long, default_value = "60")]
+        interval: u64,
+    },
+    /// Print a block from the chain and from the database
+    InspectBlock { number: u64 },
+    /// Create or upgrade the database schema
+    Migrate,
+    /// Print the loaded config as JSON, credentials are redacted
+    PrintConfig,
+}
 
 fn main() -> Result<()> {
+    let cli = Cli::parse();
+
     init_log();
-    let indexer_config = load_indexer_config("./indexer-config.toml")?;
+    let mut indexer_config = load_indexer_config(&cli.config)?;
 
     let sentry_environment = indexer_config.sentry_environment.clone().map(|e| e.into());
     let _guard = match &indexer_config.sentry_dsn {
         Some(sentry_dsn) => sentry::init((
-            sentry_dsn.as_str(),
+            sentry_dsn.expose(),
             sentry::ClientOptions {
                 release: sentry::release_name!(),
                 environment: sentry_environment,
@@ -20,8 +87,104 @@ fn main() -> Result<()> {
         None => sentry::init(()),
     };
 
-    let mut runner = Runner::new(indexer_config)?;
-    smol::block_on(runner.run())?;
+    let command = cli.command.unwrap_or(Command::Run);
+    match command {
+        Command::Migrate => {
+            if indexer_config.sqlite_url.is_some() {
+                // SQLite storage is opened with the chain id
+                smol::block_on(resolve_chain_spec(&mut indexer_config))?;
+                smol::block_on(open_storage(&indexer_config))?;
+                println!("SQLite schema is created on connect");
+                return Ok(());
+            }
+            let pool = build_pool(&indexer_config)?;
+            let version = smol::block_on(migrate(&pool))?;
+            println!("Database schema is at version {}", version);
+            return Ok(());
+        }
+        Command::PrintConfig => {
+            println!("{}", serde_json::to_string_pretty(&indexer_config)?);
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    smol::block_on(resolve_chain_spec(&mut indexer_config))?;
+    let storage = smol::block_on(open_storage(&indexer_config))?;
+    match command {
+        Command::Run => {
+            let mut runner = Runner::new(indexer_config, storage)?;
+            register_shutdown_signals(&runner)?;
+            smol::block_on(runner.run())
+        }
+        Command::Backfill { from, to } => {
+            let from = from
+                .or(indexer_config.backfill_from)
+                .ok_or_else(|| anyhow!("backfill requires --from"))?;
+            let to = to
+                .or(indexer_config.backfill_to)
+                .ok_or_else(|| anyhow!("backfill requires --to"))?;
+            let mut runner = Runner::new(indexer_config, storage)?;
+            register_shutdown_signals(&runner)?;
+            smol::block_on(runner.backfill(from, to))
+        }
+        Command::Rollback { to } => {
+            let mut runner = Runner::new(indexer_config, storage)?;
+            let reverted = smol::block_on(runner.rollback_to(to))?;
+            println!("Rollback to block {}, archived {}", to, reverted);
+            Ok(())
+        }
+        Command::Verify {
+            from,
+            to,
+            repair,
+            follow,
+            interval,
+        } => {
+            let mut verifier = Verifier::new(indexer_config, storage)?;
+            if follow {
+                let interval = Duration::from_secs(interval);
+                return smol::block_on(verifier.follow(from, repair, interval));
+            }
+            smol::block_on(verify(&mut verifier, from, to, repair))
+        }
+        Command::InspectBlock { number } => {
+            let verifier = Verifier::new(indexer_config, storage)?;
+            let block = smol::block_on(verifier.inspect_block(number))?;
+            println!("{}", serde_json::to_string_pretty(&block)?);
+            Ok(())
+        }
+        Command::Migrate | Command::PrintConfig => unreachable!("handled above"),
+    }
+}
+
+async fn verify(verifier: &mut Verifier, from: u64, to: Option<u64>, repair: bool) -> Result<()> {
+    let to = match to {
+        Some(to) => to,
+        None => match verifier.tip_number().await? {
+            Some(tip) => tip,
+            None => return Err(anyhow!("no indexed blocks to verify")),
+        },
+    };
+    let report = verifier.verify(from, to, repair).await?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if !report.is_ok() && !repair {
+        return Err(anyhow!(
+            "verification failed, missing: {}, mismatches: {}",
+            report.missing.len(),
+            report.mismatches.len()
+        ));
+    }
+    Ok(())
+}
+
+// Stop after the block being written on SIGTERM / SIGINT, a second signal kills the process
+fn register_shutdown_signals(runner: &Runner) -> Result<()> {
+    let shutdown = runner.shutdown_flag();
+    for signal in [SIGTERM, SIGINT] {
+        signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&shutdown))?;
+        signal_hook::flag::register(signal, Arc::clone(&shutdown))?;
+    }
     Ok(())
 }
 
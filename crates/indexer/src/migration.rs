@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use sqlx::{migrate::Migrator, PgPool};
+
+/// Schema migrations under `crates/indexer/migrations`, tracked in `_sqlx_migrations`.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Create or upgrade the schema, returns the schema version.
+pub async fn migrate(pool: &PgPool) -> Result<i64> {
+    MIGRATOR.run(pool).await?;
+    Ok(latest_version())
+}
+
+/// Refuse to run against a schema which isn't exactly the one this indexer was
+/// built with.
+pub async fn check_schema_version(pool: &PgPool) -> Result<()> {
+    let (initialized,): (bool,) =
+        sqlx::query_as("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+            .fetch_one(pool)
+            .await?;
+    if !initialized {
+        return Err(anyhow!(
+            "database schema is not managed by the indexer, run `gw-web3-indexer migrate` first"
+        ));
+    }
+
+    let applied: Vec<(i64, bool, Vec<u8>)> =
+        sqlx::query_as("SELECT version, success, checksum FROM _sqlx_migrations ORDER BY version")
+            .fetch_all(pool)
+            .await?;
+    let known: HashMap<i64, &[u8]> = MIGRATOR.iter().map(|m| (m.version, &*m.checksum)).collect();
+    for (version, success, checksum) in applied.iter() {
+        if !success {
+            return Err(anyhow!("schema migration {} is partially applied", version));
+        }
+        match known.get(version) {
+            None => {
+                return Err(anyhow!(
+                    "database schema version {} is newer than this indexer supports ({})",
+                    version,
+                    latest_version()
+                ))
+            }
+            Some(known_checksum) if *known_checksum != checksum.as_slice() => {
+                return Err(anyhow!(
+                    "schema migration {} differs from the one applied to the database",
+                    version
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+
+    let applied_version = applied.last().map(|(v, _, _)| *v).unwrap_or(0);
+    if applied.len() < known.len() {
+        return Err(anyhow!(
+            "database schema version {} is behind {}, run `gw-web3-indexer migrate` first",
+            applied_version,
+            latest_version()
+        ));
+    }
+    Ok(())
+}
+
+pub fn latest_version() -> i64 {
+    MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0)
+}
@@ -1,21 +1,35 @@
-use std::time::Duration;
+use std::{str::FromStr, time::Duration};
 
+use anyhow::{anyhow, Result};
 use sqlx::{
     postgres::{PgConnectOptions, PgPoolOptions},
     ConnectOptions, PgPool,
 };
 
-use crate::config::load_indexer_config;
+use crate::config::IndexerConfig;
 
-lazy_static::lazy_static! {
-    pub static ref POOL: PgPool = {
-        let indexer_config = load_indexer_config("./indexer-config.toml").unwrap();
+/// Build the Postgres pool from config. Connections are opened lazily, so this
+/// doesn't need a running database.
+pub fn build_pool(config: &IndexerConfig) -> Result<PgPool> {
+    let mut opts: PgConnectOptions = config
+        .pg_url
+        .expose()
+        .parse()
+        .map_err(|err| anyhow!("parse \"pg_url\" {}: {}", config.pg_url, err))?;
+    let log_statements = log::LevelFilter::from_str(&config.pg_log_statements)?;
+    opts.log_statements(log_statements).log_slow_statements(
+        log::LevelFilter::Warn,
+        Duration::from_millis(config.pg_slow_statement_threshold_ms),
+    );
 
-        let mut opts: PgConnectOptions = indexer_config.pg_url.parse().expect("pg url parse error");
-        opts.log_statements(log::LevelFilter::Debug)
-            .log_slow_statements(log::LevelFilter::Warn, Duration::from_secs(5));
-        PgPoolOptions::new()
-            .max_connections(5)
-            .connect_lazy_with(opts)
+    let idle_timeout = match config.pg_idle_timeout_secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
     };
+    let pool = PgPoolOptions::new()
+        .max_connections(config.pg_max_connections)
+        .acquire_timeout(Duration::from_secs(config.pg_acquire_timeout_secs))
+        .idle_timeout(idle_timeout)
+        .connect_lazy_with(opts);
+    Ok(pool)
 }
@@ -0,0 +1,201 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use anyhow::Result;
+use futures::{future::BoxFuture, FutureExt};
+use gw_types::packed::L2Block;
+use gw_web3_rpc_client::{convertion::to_l2_block, godwoken_async_client::GodwokenAsyncClient};
+use smol::Task;
+
+// Fetches a block by number, None if it doesn't exist yet
+type FetchBlock = Arc<dyn Fn(u64) -> BoxFuture<'static, Result<Option<L2Block>>> + Send + Sync>;
+
+/// Fetch and decode upcoming blocks while the current one is being written.
+///
+/// Blocks are handed out strictly in the order they are requested. The window
+/// is only filled while the requested blocks exist, so once the runner reaches
+/// the chain tip it falls back to fetching a single block per request.
+pub struct BlockPrefetcher {
+    fetch_block: FetchBlock,
+    // Max number of blocks fetched ahead of the requested one, 0 disables prefetching
+    window: usize,
+    // Never fetch ahead past this block
+    last_block_number: Option<u64>,
+    pending: VecDeque<(u64, Task<Result<Option<L2Block>>>)>,
+}
+
+impl BlockPrefetcher {
+    pub fn new(godwoken_async_client: Arc<GodwokenAsyncClient>, window: usize) -> Self {
+        let fetch_block: FetchBlock = Arc::new(move |block_number: u64| {
+            let godwoken_async_client = Arc::clone(&godwoken_async_client);
+            async move {
+                let block = godwoken_async_client
+                    .get_block_by_number(block_number)
+                    .await?;
+                Ok::<_, anyhow::Error>(block.map(to_l2_block))
+            }
+            .boxed()
+        });
+        Self::with_fetch_block(fetch_block, window)
+    }
+
+    fn with_fetch_block(fetch_block: FetchBlock, window: usize) -> Self {
+        BlockPrefetcher {
+            fetch_block,
+            window,
+            last_block_number: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn set_last_block_number(&mut self, last_block_number: Option<u64>) {
+        self.last_block_number = last_block_number;
+    }
+
+    /// Drop all in-flight fetches, e.g. after the local tip was rolled back.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+
+    pub async fn fetch(&mut self, block_number: u64) -> Result<Option<L2Block>> {
+        match self.pending.front() {
+            Some((number, _)) if *number == block_number => {}
+            _ => {
+                self.reset();
+                self.spawn(block_number);
+            }
+        }
+
+        let (_, block_task) = self.pending.pop_front().expect("pending block fetch");
+        let block = match block_task.await {
+            Ok(block) => block,
+            Err(err) => {
+                self.reset();
+                return Err(err);
+            }
+        };
+
+        if block.is_some() {
+            // Still catching up, keep the window full
+            while self.pending.len() < self.window {
+                let next_block_number = block_number + 1 + self.pending.len() as u64;
+                if matches!(self.last_block_number, Some(last) if next_block_number > last) {
+                    break;
+                }
+                self.spawn(next_block_number);
+            }
+        } else {
+            self.reset();
+        }
+
+        Ok(block)
+    }
+
+    fn spawn(&mut self, block_number: u64) {
+        let block_task = smol::spawn((self.fetch_block)(block_number));
+        self.pending.push_back((block_number, block_task));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use gw_types::{packed::RawL2Block, prelude::*};
+
+    use super::*;
+
+    // Serves blocks up to `tip` and records the requested block numbers
+    fn prefetcher(tip: u64, window: usize) -> (BlockPrefetcher, Arc<Mutex<Vec<u64>>>) {
+        let requested = Arc::new(Mutex::new(vec![]));
+        let requested_clone = Arc::clone(&requested);
+        let fetch_block: FetchBlock = Arc::new(move |block_number: u64| {
+            requested_clone.lock().unwrap().push(block_number);
+            async move {
+                if block_number > tip {
+                    return Ok(None);
+                }
+                let raw = RawL2Block::new_builder()
+                    .number(block_number.pack())
+                    .build();
+                Ok::<_, anyhow::Error>(Some(L2Block::new_builder().raw(raw).build()))
+            }
+            .boxed()
+        });
+        (
+            BlockPrefetcher::with_fetch_block(fetch_block, window),
+            requested,
+        )
+    }
+
+    fn fetch_number(prefetcher: &mut BlockPrefetcher, block_number: u64) -> Option<u64> {
+        smol::block_on(prefetcher.fetch(block_number))
+            .unwrap()
+            .map(|block| block.raw().number().unpack())
+    }
+
+    fn pending(prefetcher: &BlockPrefetcher) -> Vec<u64> {
+        prefetcher
+            .pending
+            .iter()
+            .map(|(number, _)| *number)
+            .collect()
+    }
+
+    #[test]
+    fn fill_window_in_order() {
+        let (mut prefetcher, requested) = prefetcher(100, 3);
+        assert_eq!(fetch_number(&mut prefetcher, 10), Some(10));
+        assert_eq!(pending(&prefetcher), vec![11, 12, 13]);
+
+        for block_number in 11..=15 {
+            assert_eq!(
+                fetch_number(&mut prefetcher, block_number),
+                Some(block_number)
+            );
+        }
+        assert_eq!(pending(&prefetcher), vec![16, 17, 18]);
+        assert_eq!(*requested.lock().unwrap(), (10..=18).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn stop_window_at_last_block_number() {
+        let (mut prefetcher, _) = prefetcher(100, 3);
+        prefetcher.set_last_block_number(Some(11));
+        assert_eq!(fetch_number(&mut prefetcher, 10), Some(10));
+        assert_eq!(pending(&prefetcher), vec![11]);
+        assert_eq!(fetch_number(&mut prefetcher, 11), Some(11));
+        assert!(pending(&prefetcher).is_empty());
+    }
+
+    #[test]
+    fn reset_on_out_of_order_request() {
+        let (mut prefetcher, requested) = prefetcher(100, 2);
+        assert_eq!(fetch_number(&mut prefetcher, 10), Some(10));
+        assert_eq!(fetch_number(&mut prefetcher, 5), Some(5));
+        assert_eq!(pending(&prefetcher), vec![6, 7]);
+        assert_eq!(*requested.lock().unwrap(), vec![10, 11, 12, 5, 6, 7]);
+
+        prefetcher.reset();
+        assert!(pending(&prefetcher).is_empty());
+        assert_eq!(fetch_number(&mut prefetcher, 6), Some(6));
+        assert_eq!(pending(&prefetcher), vec![7, 8]);
+    }
+
+    #[test]
+    fn reset_at_chain_tip() {
+        let (mut prefetcher, _) = prefetcher(11, 3);
+        assert_eq!(fetch_number(&mut prefetcher, 10), Some(10));
+        assert_eq!(fetch_number(&mut prefetcher, 11), Some(11));
+        assert_eq!(fetch_number(&mut prefetcher, 12), None);
+        assert!(pending(&prefetcher).is_empty());
+    }
+
+    #[test]
+    fn disabled_window_fetches_one_block() {
+        let (mut prefetcher, requested) = prefetcher(100, 0);
+        assert_eq!(fetch_number(&mut prefetcher, 10), Some(10));
+        assert_eq!(fetch_number(&mut prefetcher, 11), Some(11));
+        assert!(pending(&prefetcher).is_empty());
+        assert_eq!(*requested.lock().unwrap(), vec![10, 11]);
+    }
+}
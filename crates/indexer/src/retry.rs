@@ -0,0 +1,351 @@
+use std::{
+    cmp,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Error, Result};
+use gw_web3_rpc_client::error::RpcClientError;
+use rand::Rng;
+use thiserror::Error;
+
+use crate::config::IndexerConfig;
+
+// JSON-RPC internal error and the implementation defined server error range
+const JSONRPC_INTERNAL_ERROR: i64 = -32603;
+const JSONRPC_SERVER_ERROR_RANGE: std::ops::RangeInclusive<i64> = -32099..=-32000;
+// How often a backoff checks for shutdown
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    // Worth retrying, e.g. connection drops and timeouts
+    Transient,
+    // Retrying won't help, e.g. malformed data and constraint violations
+    Fatal,
+}
+
+/// Marks an error which may go away by itself, e.g. a receipt the node hasn't served yet.
+#[derive(Error, Debug)]
+#[error("{0}")]
+pub struct TransientError(pub String);
+
+/// Classify an error by the first known cause in its chain, unknown errors are fatal.
+pub fn classify(err: &Error) -> ErrorKind {
+    for cause in err.chain() {
+        if cause.is::<TransientError>() || cause.is::<std::io::Error>() {
+            return ErrorKind::Transient;
+        }
+        if let Some(err) = cause.downcast_ref::<RpcClientError>() {
+            return classify_rpc_client_error(err);
+        }
+        if let Some(err) = cause.downcast_ref::<sqlx::Error>() {
+            return classify_sqlx_error(err);
+        }
+    }
+    ErrorKind::Fatal
+}
+
+fn classify_rpc_client_error(err: &RpcClientError) -> ErrorKind {
+    match err {
+        RpcClientError::ConnectionError(_, _) => ErrorKind::Transient,
+        RpcClientError::JsonRpcError { code, .. }
+            if *code == JSONRPC_INTERNAL_ERROR || JSONRPC_SERVER_ERROR_RANGE.contains(code) =>
+        {
+            ErrorKind::Transient
+        }
+        RpcClientError::Other(err) => classify(err),
+        _ => ErrorKind::Fatal,
+    }
+}
+
+fn classify_sqlx_error(err: &sqlx::Error) -> ErrorKind {
+    match err {
+        sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::PoolTimedOut => {
+            ErrorKind::Transient
+        }
+        sqlx::Error::Database(db_err) => match db_err.code() {
+            // Connection exceptions, serialization failure, deadlock, too many
+            // connections, admin / crash shutdown and cannot connect now
+            Some(code)
+                if code.starts_with("08")
+                    || matches!(
+                        code.as_ref(),
+                        "40001" | "40P01" | "53300" | "57P01" | "57P02" | "57P03"
+                    ) =>
+            {
+                ErrorKind::Transient
+            }
+            _ => ErrorKind::Fatal,
+        },
+        _ => ErrorKind::Fatal,
+    }
+}
+
+/// Exponential backoff with jitter, shared by every retried operation of the indexer.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    // Retries of a single operation, 0 disables retrying
+    max_retries: u32,
+    initial_interval: Duration,
+    max_interval: Duration,
+    // Retries since start, across all operations
+    total_retries: Arc<AtomicU64>,
+    // A backoff ends early once it is set
+    shutdown: Option<Arc<AtomicBool>>,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32, initial_interval: Duration, max_interval: Duration) -> Self {
+        RetryPolicy {
+            max_retries,
+            initial_interval,
+            max_interval,
+            total_retries: Arc::new(AtomicU64::new(0)),
+            shutdown: None,
+        }
+    }
+
+    /// End backoffs early once `shutdown` is set, the caller checks it after the backoff.
+    pub fn with_shutdown(mut self, shutdown: Arc<AtomicBool>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    pub fn from_config(config: &IndexerConfig) -> Self {
+        Self::new(
+            config.retry_max_retries,
+            Duration::from_millis(config.retry_initial_interval_ms),
+            Duration::from_millis(config.retry_max_interval_ms),
+        )
+    }
+
+    pub fn total_retries(&self) -> u64 {
+        self.total_retries.load(Ordering::Relaxed)
+    }
+
+    // Delay before the next attempt, None means give up. Without `max_retries` transient
+    // errors are retried forever.
+    fn next_delay(&self, err: &Error, retries: u32, max_retries: Option<u32>) -> Option<Duration> {
+        if matches!(max_retries, Some(max) if retries >= max) || classify(err) == ErrorKind::Fatal {
+            return None;
+        }
+        self.total_retries.fetch_add(1, Ordering::Relaxed);
+        Some(self.delay(retries))
+    }
+
+    // Random delay in [interval / 2, interval], the interval doubles on every retry
+    fn delay(&self, retries: u32) -> Duration {
+        let interval = self
+            .initial_interval
+            .checked_mul(1u32.checked_shl(retries).unwrap_or(u32::MAX))
+            .unwrap_or(self.max_interval);
+        let interval = cmp::min(interval, self.max_interval).as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(interval / 2..=interval))
+    }
+
+    /// Sleep before the next attempt of a failed operation, or hand the error back if
+    /// it shouldn't be retried. `retries` is bumped on every retry.
+    pub async fn backoff(&self, name: &str, err: Error, retries: &mut u32) -> Result<()> {
+        self.backoff_with(name, err, retries, Some(self.max_retries))
+            .await
+    }
+
+    /// Like `backoff`, but transient errors are retried until they go away, the delay
+    /// stays capped at the max interval. For the main loop, which has to outlive an
+    /// outage of Godwoken or Postgres.
+    pub async fn backoff_unlimited(&self, name: &str, err: Error, retries: &mut u32) -> Result<()> {
+        self.backoff_with(name, err, retries, None).await
+    }
+
+    async fn backoff_with(
+        &self,
+        name: &str,
+        err: Error,
+        retries: &mut u32,
+        max_retries: Option<u32>,
+    ) -> Result<()> {
+        match self.next_delay(&err, *retries, max_retries) {
+            Some(delay) => {
+                *retries = retries.saturating_add(1);
+                match max_retries {
+                    Some(max) => log::warn!(
+                        "{} failed, retry {}/{} in {:?}: {}",
+                        name,
+                        retries,
+                        max,
+                        delay,
+                        err
+                    ),
+                    None => {
+                        log::warn!("{} failed, retry {} in {:?}: {}", name, retries, delay, err)
+                    }
+                }
+                self.sleep(delay).await;
+                Ok(())
+            }
+            None => Err(err),
+        }
+    }
+
+    // Sleep for `delay` or until shutdown
+    async fn sleep(&self, delay: Duration) {
+        let shutdown = match &self.shutdown {
+            Some(shutdown) => shutdown,
+            None => {
+                smol::Timer::after(delay).await;
+                return;
+            }
+        };
+        let deadline = Instant::now() + delay;
+        while !shutdown.load(Ordering::SeqCst) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return;
+            }
+            smol::Timer::after(cmp::min(remaining, SHUTDOWN_CHECK_INTERVAL)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    fn json_rpc_error(code: i64) -> Error {
+        RpcClientError::JsonRpcError {
+            method: "gw_get_block".to_string(),
+            code,
+            message: "error".to_string(),
+        }
+        .into()
+    }
+
+    fn policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy::new(
+            max_retries,
+            Duration::from_millis(1),
+            Duration::from_millis(4),
+        )
+    }
+
+    #[test]
+    fn classify_transient_errors() {
+        let io_error = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset");
+        let errors = vec![
+            TransientError("receipt not found".to_string()).into(),
+            Error::from(io_error).context("fetch block"),
+            RpcClientError::ConnectionError("gw_get_block".to_string(), anyhow!("refused")).into(),
+            json_rpc_error(JSONRPC_INTERNAL_ERROR),
+            json_rpc_error(-32000),
+            json_rpc_error(-32099),
+            RpcClientError::Other(TransientError("busy".to_string()).into()).into(),
+            sqlx::Error::PoolTimedOut.into(),
+        ];
+        for err in errors {
+            assert_eq!(classify(&err), ErrorKind::Transient, "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn classify_fatal_errors() {
+        let errors = vec![
+            anyhow!("unknown"),
+            json_rpc_error(-32601),
+            json_rpc_error(-32100),
+            RpcClientError::Other(anyhow!("malformed block")).into(),
+            sqlx::Error::RowNotFound.into(),
+            Error::from(sqlx::Error::ColumnNotFound("number".to_string())).context("insert"),
+        ];
+        for err in errors {
+            assert_eq!(classify(&err), ErrorKind::Fatal, "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn delay_doubles_and_is_capped() {
+        let policy = RetryPolicy::new(10, Duration::from_millis(100), Duration::from_millis(1000));
+        for (retries, interval) in [
+            (0, 100),
+            (1, 200),
+            (2, 400),
+            (3, 800),
+            (4, 1000),
+            (40, 1000),
+        ] {
+            for _ in 0..20 {
+                let delay = policy.delay(retries).as_millis() as u64;
+                assert!(
+                    (interval / 2..=interval).contains(&delay),
+                    "retry {} delay {}ms",
+                    retries,
+                    delay
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn backoff_gives_up_after_max_retries() {
+        let policy = policy(2);
+        let mut retries = 0;
+        smol::block_on(async {
+            for _ in 0..2 {
+                let err = TransientError("timeout".to_string()).into();
+                policy.backoff("Test", err, &mut retries).await.unwrap();
+            }
+            let err = TransientError("timeout".to_string()).into();
+            assert!(policy.backoff("Test", err, &mut retries).await.is_err());
+        });
+        assert_eq!(retries, 2);
+        assert_eq!(policy.total_retries(), 2);
+    }
+
+    #[test]
+    fn backoff_returns_fatal_errors() {
+        let policy = policy(2);
+        let mut retries = 0;
+        let err = smol::block_on(policy.backoff("Test", anyhow!("fatal"), &mut retries));
+        assert_eq!(err.unwrap_err().to_string(), "fatal");
+        assert_eq!(retries, 0);
+        assert_eq!(policy.total_retries(), 0);
+    }
+
+    #[test]
+    fn backoff_unlimited_ignores_max_retries() {
+        let policy = policy(0);
+        let mut retries = 0;
+        smol::block_on(async {
+            for _ in 0..5 {
+                let err = TransientError("timeout".to_string()).into();
+                policy
+                    .backoff_unlimited("Test", err, &mut retries)
+                    .await
+                    .unwrap();
+            }
+            let err = anyhow!("fatal");
+            assert!(policy
+                .backoff_unlimited("Test", err, &mut retries)
+                .await
+                .is_err());
+        });
+        assert_eq!(retries, 5);
+    }
+
+    #[test]
+    fn shutdown_ends_backoff_early() {
+        let shutdown = Arc::new(AtomicBool::new(true));
+        let policy = RetryPolicy::new(1, Duration::from_secs(60), Duration::from_secs(60))
+            .with_shutdown(shutdown);
+        let mut retries = 0;
+        let start = Instant::now();
+        let err = TransientError("timeout".to_string()).into();
+        smol::block_on(policy.backoff("Test", err, &mut retries)).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -1,36 +1,196 @@
-use ckb_types::prelude::Entity;
-use gw_web3_rpc_client::{
-    convertion::to_l2_block, error::RpcClientError, godwoken_rpc_client::GodwokenRpcClient,
+use std::{
+    cmp,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
-use rust_decimal::{prelude::ToPrimitive, Decimal};
 
-use crate::{config::IndexerConfig, pool::POOL, Web3Indexer};
-use anyhow::Result;
+use ckb_hash::blake2b_256;
+use ckb_types::{prelude::Entity, H256};
+use gw_types::{packed::L2Block, prelude::Unpack};
+use gw_web3_rpc_client::{godwoken_async_client::GodwokenAsyncClient, tip_poller::TipPoller};
+use sqlx::types::chrono::{self, Utc};
+
+use crate::{
+    config::IndexerConfig,
+    finality::FinalityTracker,
+    helper::hex,
+    insert_l2_block::UpsertCounts,
+    lock::{acquire_writer, try_acquire_writer, WriterLock},
+    prefetch::BlockPrefetcher,
+    retry::{RetryPolicy, TransientError},
+    storage::{require_pg_pool, DeletedRows, PrunedRows, Storage},
+    Web3Indexer,
+};
+use anyhow::{anyhow, Result};
+
+const WRITER_LOCK_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+const ORPHAN_PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+const HISTORY_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+const STOPPED_TIP_TIMEOUT: Duration = Duration::from_secs(2);
 
 pub struct Runner {
     indexer: Web3Indexer,
     local_tip: Option<u64>,
-    godwoken_rpc_client: GodwokenRpcClient,
+    godwoken_async_client: Arc<GodwokenAsyncClient>,
+    prefetcher: BlockPrefetcher,
+    max_reorg_depth: u64,
+    start_block_number: Option<u64>,
+    start_block_hash: Option<H256>,
+    rollup_type_hash: H256,
+    writer_lock_enabled: bool,
+    writer_lock: Option<WriterLock>,
+    retry_policy: RetryPolicy,
+    // None disables finality tracking
+    finality_check_interval: Option<Duration>,
+    block_poll_interval: Duration,
+    // None only polls for new blocks
+    tip_poll_interval: Option<Duration>,
+    // None keeps reverted blocks forever
+    orphan_retention: Option<Duration>,
+    last_orphan_prune: Option<Instant>,
+    // None disables pruning of logs and transaction inputs
+    prune_keep_blocks: Option<u64>,
+    prune_batch_blocks: u64,
+    // Set once pruning caught up with the tip
+    last_history_prune: Option<Instant>,
+    // Set by signal handlers, checked between blocks and during backoffs
+    shutdown: Arc<AtomicBool>,
+    storage: Arc<dyn Storage>,
 }
 
 impl Runner {
-    pub fn new(config: IndexerConfig) -> Result<Runner> {
-        let indexer = Web3Indexer::new(
-            config.l2_sudt_type_script_hash,
-            config.polyjuice_type_script_hash,
-            config.rollup_type_hash,
-            config.eth_account_lock_hash,
+    pub fn new(config: IndexerConfig, storage: Arc<dyn Storage>) -> Result<Runner> {
+        let godwoken_async_client = Arc::new(GodwokenAsyncClient::with_url(
             config.godwoken_rpc_url.as_str(),
+        )?);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let retry_policy = RetryPolicy::from_config(&config).with_shutdown(Arc::clone(&shutdown));
+        let chain_spec = config.chain_spec()?;
+        let indexer = Web3Indexer::new(
+            chain_spec.l2_sudt_type_script_hash,
+            chain_spec.polyjuice_type_script_hash,
+            chain_spec.rollup_type_hash.clone(),
+            chain_spec.eth_account_lock_hash,
+            Arc::clone(&godwoken_async_client),
+            Arc::clone(&storage),
         );
-        let godwoken_rpc_client = GodwokenRpcClient::new(config.godwoken_rpc_url.as_str());
+        let prefetcher =
+            BlockPrefetcher::new(Arc::clone(&godwoken_async_client), config.prefetch_window);
         let runner = Runner {
             indexer,
             local_tip: None,
-            godwoken_rpc_client,
+            godwoken_async_client,
+            prefetcher,
+            max_reorg_depth: config.max_reorg_depth,
+            start_block_number: config.start_block_number,
+            start_block_hash: config.start_block_hash,
+            rollup_type_hash: chain_spec.rollup_type_hash,
+            // Only Postgres can be shared by several indexers
+            writer_lock_enabled: config.writer_lock && storage.pg_pool().is_some(),
+            writer_lock: None,
+            retry_policy,
+            finality_check_interval: match (config.finality_check_interval_secs, storage.pg_pool())
+            {
+                (0, _) | (_, None) => None,
+                (secs, Some(_)) => Some(Duration::from_secs(secs)),
+            },
+            block_poll_interval: Duration::from_millis(config.block_poll_interval_ms),
+            tip_poll_interval: config.tip_poll_interval_ms.map(Duration::from_millis),
+            orphan_retention: match config.orphan_retention_days {
+                0 => None,
+                days => Some(Duration::from_secs(days * 24 * 3600)),
+            },
+            last_orphan_prune: None,
+            prune_keep_blocks: match config.prune_keep_blocks {
+                0 => None,
+                blocks => Some(blocks),
+            },
+            prune_batch_blocks: config.prune_batch_blocks,
+            last_history_prune: None,
+            shutdown,
+            storage,
         };
         Ok(runner)
     }
 
+    /// Flag to request a graceful stop, the block being written is finished first.
+    pub fn shutdown_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.shutdown)
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    async fn log_stopped(&self) {
+        // The cursor is reset while waiting for the writer lock, the stored tip is accurate
+        // unless the database is down
+        let stored_tip = smol::future::or(async { Some(self.get_db_tip_number().await) }, async {
+            smol::Timer::after(STOPPED_TIP_TIMEOUT).await;
+            None
+        })
+        .await;
+        let tip = match stored_tip {
+            Some(Ok(tip)) => tip,
+            Some(Err(err)) => {
+                log::warn!("Read the indexed tip failed: {}", err);
+                self.local_tip
+            }
+            None => self.local_tip,
+        };
+        match tip {
+            Some(tip) => log::info!(
+                "Stopped at block {}, retried {} times",
+                tip,
+                self.retry_policy.total_retries()
+            ),
+            None => log::info!("Stopped before indexing any block"),
+        }
+    }
+
+    /// Wait until this runner becomes the only writer of the database.
+    pub async fn acquire_writer_lock(&mut self) -> Result<()> {
+        if !self.writer_lock_enabled || self.writer_lock.is_some() {
+            return Ok(());
+        }
+
+        let lock = acquire_writer(
+            self.storage.as_ref(),
+            &self.rollup_type_hash,
+            WRITER_LOCK_RETRY_INTERVAL,
+            &self.shutdown,
+        )
+        .await?
+        .ok_or_else(|| anyhow!("shutdown while waiting for writer lock"))?;
+        self.hold_writer_lock(lock);
+
+        Ok(())
+    }
+
+    /// Become the only writer of the database, fails right away if another indexer is the
+    /// writer. For one-off commands, which shouldn't wait behind a running indexer.
+    pub async fn try_acquire_writer_lock(&mut self, command: &str) -> Result<()> {
+        if !self.writer_lock_enabled || self.writer_lock.is_some() {
+            return Ok(());
+        }
+
+        let lock =
+            try_acquire_writer(self.storage.as_ref(), &self.rollup_type_hash, command).await?;
+        self.hold_writer_lock(lock);
+
+        Ok(())
+    }
+
+    fn hold_writer_lock(&mut self, lock: WriterLock) {
+        self.writer_lock = Some(lock);
+        // Another writer may have moved the tip while we were waiting
+        self.local_tip = None;
+        self.prefetcher.reset();
+    }
+
     // None means no local blocks
     pub async fn tip(&self) -> Result<Option<u64>> {
         let tip = match self.local_tip {
@@ -40,132 +200,222 @@ impl Runner {
         Ok(tip)
     }
 
-    pub async fn bump_tip(&mut self) -> Result<()> {
-        match self.local_tip {
-            None => {
-                self.local_tip = if let Some(n) = self.get_db_tip_number().await? {
-                    Some(n)
-                } else {
-                    Some(0)
-                }
-            }
-            Some(t) => {
-                self.local_tip = Some(t + 1);
-            }
+    async fn get_db_tip_number(&self) -> Result<Option<u64>> {
+        self.storage.tip_number().await
+    }
+
+    async fn get_db_block_hash(&self, block_number: u64) -> Result<Option<H256>> {
+        self.storage.block_hash(block_number).await
+    }
+
+    // Move blocks whose number >= `from_block_number` to the orphan tables in one db
+    // transaction
+    async fn revert_blocks_from(&self, from_block_number: u64) -> Result<DeletedRows> {
+        self.storage.revert_blocks_from(from_block_number).await
+    }
+
+    // Delete orphaned rows older than the retention, at most once per interval
+    async fn prune_orphans(&mut self) -> Result<()> {
+        let retention = match self.orphan_retention {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        if matches!(self.last_orphan_prune, Some(t) if t.elapsed() < ORPHAN_PRUNE_INTERVAL) {
+            return Ok(());
         }
+        self.last_orphan_prune = Some(Instant::now());
 
+        let reverted_before = Utc::now() - chrono::Duration::from_std(retention)?;
+        let pruned = self.storage.prune_orphans(reverted_before).await?;
+        if pruned != DeletedRows::default() {
+            log::info!(
+                "Pruned orphans reverted before {}: {}",
+                reverted_before,
+                pruned
+            );
+        }
         Ok(())
     }
 
-    pub fn revert_tip(&mut self) -> Result<()> {
-        if let Some(t) = self.local_tip {
-            if t == 0 {
-                self.local_tip = None;
-            } else {
-                self.local_tip = Some(t - 1);
-            }
+    // Prune one batch of logs and transaction inputs below the kept blocks, once caught
+    // up wait an interval before checking again
+    async fn prune_history(&mut self) -> Result<()> {
+        let keep_blocks = match self.prune_keep_blocks {
+            Some(k) => k,
+            None => return Ok(()),
+        };
+        if matches!(self.last_history_prune, Some(t) if t.elapsed() < HISTORY_PRUNE_INTERVAL) {
+            return Ok(());
+        }
+        let below = match self.tip().await? {
+            Some(tip) => (tip + 1).saturating_sub(keep_blocks),
+            None => 0,
+        };
+        let from = match self.storage.pruned_below().await? {
+            Some(n) => n,
+            None => self.start_block_number.unwrap_or(0),
+        };
+        if from >= below {
+            self.last_history_prune = Some(Instant::now());
+            return Ok(());
         }
 
+        let batch_below = cmp::min(below, from + self.prune_batch_blocks);
+        let pruned = self.storage.prune_history(from, batch_below).await?;
+        if pruned != PrunedRows::default() {
+            log::info!(
+                "Pruned history of blocks {}..{}: {}",
+                from,
+                batch_below,
+                pruned
+            );
+        }
         Ok(())
     }
 
-    async fn get_db_tip_number(&self) -> Result<Option<u64>> {
-        let row: Option<(Decimal,)> =
-            sqlx::query_as("select number from blocks order by number desc limit 1;")
-                .fetch_optional(&*POOL)
+    // Walk back from an orphaned local block until the db hash matches the chain hash again.
+    // None means every local block up to `orphan_block_number` is orphaned.
+    async fn find_fork_point(&self, orphan_block_number: u64) -> Result<Option<u64>> {
+        let mut block_number = orphan_block_number;
+        while block_number > 0 {
+            block_number -= 1;
+            self.check_reorg_depth(orphan_block_number, Some(block_number))?;
+            if let Some(start_block_number) = self.start_block_number {
+                if block_number < start_block_number {
+                    return Err(anyhow!(
+                        "fork point is below the start block {}",
+                        start_block_number
+                    ));
+                }
+            }
+
+            let db_block_hash = self.get_db_block_hash(block_number).await?;
+            let chain_block_hash = self
+                .godwoken_async_client
+                .get_block_hash(block_number)
                 .await?;
+            if db_block_hash.is_some() && db_block_hash == chain_block_hash {
+                return Ok(Some(block_number));
+            }
+        }
+        self.check_reorg_depth(orphan_block_number, None)?;
+        if let Some(start_block_number) = self.start_block_number {
+            return Err(anyhow!(
+                "fork point is below the start block {}",
+                start_block_number
+            ));
+        }
 
-        let num = row.and_then(|(n,)| n.to_u64());
-        Ok(num)
+        Ok(None)
     }
 
-    async fn get_db_block_hash(&self, block_number: u64) -> Result<Option<ckb_types::H256>> {
-        let row: Option<(Vec<u8>,)> =
-            sqlx::query_as("select hash from blocks where number = $1 limit 1;")
-                .bind(Decimal::from(block_number))
-                .fetch_optional(&*POOL)
-                .await?;
-
-        if let Some((block_hash_vec,)) = row {
-            let block_hash = ckb_types::H256::from_slice(block_hash_vec.as_ref())?;
-            return Ok(Some(block_hash));
+    fn check_reorg_depth(&self, local_tip: u64, fork_point: Option<u64>) -> Result<()> {
+        let depth = match fork_point {
+            Some(n) => local_tip - n,
+            None => local_tip + 1,
+        };
+        if depth > self.max_reorg_depth {
+            log::error!(
+                "Reorg deeper than max_reorg_depth {} detected at local tip {}, stop indexing",
+                self.max_reorg_depth,
+                local_tip
+            );
+            return Err(anyhow!(
+                "reorg depth exceeds max_reorg_depth {} at local tip {}",
+                self.max_reorg_depth,
+                local_tip
+            ));
         }
-        Ok(None)
+        Ok(())
     }
 
-    async fn delete_block(&self, block_number: u64) -> Result<()> {
-        let number = Decimal::from(block_number);
-        let pool = &*POOL;
-        let mut tx = pool.begin().await?;
-        sqlx::query("delete from logs where block_number = $1;")
-            .bind(number)
-            .execute(&mut tx)
-            .await?;
-        sqlx::query("delete from transactions where block_number = $1;")
-            .bind(number)
-            .execute(&mut tx)
-            .await?;
-        sqlx::query("delete from blocks where number = $1;")
-            .bind(number)
-            .execute(&mut tx)
-            .await?;
-        tx.commit().await?;
+    async fn rollback(&mut self, local_tip: u64) -> Result<()> {
+        let fork_point = self.find_fork_point(local_tip).await?;
+        let from_block_number = fork_point.map(|n| n + 1).unwrap_or(0);
+        let reverted = self.revert_blocks_from(from_block_number).await?;
+        log::info!(
+            "Rollback blocks {}..={}, fork point: {:?}, archived: {}",
+            from_block_number,
+            local_tip,
+            fork_point,
+            reverted
+        );
+        self.local_tip = fork_point;
+        // Prefetched blocks may belong to the reverted fork
+        self.prefetcher.reset();
+
         Ok(())
     }
 
+    /// Move every indexed block above `block_number` to the orphan tables in one db
+    /// transaction. Fails right away while another indexer holds the writer lock.
+    pub async fn rollback_to(&mut self, block_number: u64) -> Result<DeletedRows> {
+        // Nothing can be indexed above u64::MAX
+        let from_block_number = match block_number.checked_add(1) {
+            Some(n) => n,
+            None => return Ok(DeletedRows::default()),
+        };
+        self.try_acquire_writer_lock("rolling back").await?;
+        let reverted = self.revert_blocks_from(from_block_number).await?;
+        log::info!("Rollback to block {}, archived: {}", block_number, reverted);
+        self.local_tip = self.get_db_tip_number().await?;
+        self.prefetcher.reset();
+
+        Ok(reverted)
+    }
+
     pub async fn insert(&mut self) -> Result<bool> {
-        let start = std::time::Instant::now();
+        let start = Instant::now();
 
         let local_tip = self.tip().await?;
         let current_block_number = match local_tip {
-            None => 0,
+            None => self.start_block_number.unwrap_or(0),
             Some(t) => t + 1,
         };
 
-        let current_block = self
-            .godwoken_rpc_client
-            .get_block_by_number(current_block_number)?;
+        let current_block = self.prefetcher.fetch(current_block_number).await?;
 
-        if let Some(b) = current_block {
-            let l2_block = to_l2_block(b);
+        if let Some(l2_block) = current_block {
             let l2_block_parent_hash = l2_block.raw().parent_block_hash();
 
-            if current_block_number > 0 {
-                let prev_block_number = current_block_number - 1;
-                let db_prev_block_hash = self.get_db_block_hash(prev_block_number).await?;
-                if let Some(prev_block_hash) = db_prev_block_hash {
-                    // if match, insert a new block
-                    // if not match, delete prev block
-                    if l2_block_parent_hash.as_slice() == prev_block_hash.as_bytes() {
-                        let (txs_len, logs_len) = self.indexer.store_l2_block(l2_block).await?;
-
-                        let duration = start.elapsed();
-                        log::info!(
-                            "Sync block {}, {} txs, {} logs, duration: {:?}",
-                            current_block_number,
-                            txs_len,
-                            logs_len,
-                            duration,
-                        );
-                        self.bump_tip().await?;
+            match local_tip {
+                Some(prev_block_number) => {
+                    let db_prev_block_hash = self.get_db_block_hash(prev_block_number).await?;
+                    if let Some(prev_block_hash) = db_prev_block_hash {
+                        // if match, insert a new block
+                        // if not match, rollback to the fork point
+                        if l2_block_parent_hash.as_slice() == prev_block_hash.as_bytes() {
+                            self.store_l2_block(l2_block, start).await?;
+                        } else {
+                            self.rollback(prev_block_number).await?;
+                        }
                     } else {
-                        self.delete_block(prev_block_number).await?;
-                        log::info!("Rollback block {}", prev_block_number);
-                        self.revert_tip()?;
+                        // The cursor is stale, e.g. the block was reverted by another process.
+                        // Reload it from storage after a backoff instead of spinning
+                        self.local_tip = None;
+                        self.prefetcher.reset();
+                        return Err(TransientError(format!(
+                            "local tip {} is not in storage",
+                            prev_block_number
+                        ))
+                        .into());
+                    }
+                }
+                None => {
+                    // No local blocks, the first block is stored as the trusted base
+                    if let Some(expected_hash) = &self.start_block_hash {
+                        let block_hash = blake2b_256(l2_block.raw().as_slice());
+                        if block_hash != expected_hash.0 {
+                            return Err(anyhow!(
+                                "start block {} hash mismatch, expected: 0x{}, got: {}",
+                                current_block_number,
+                                expected_hash,
+                                hex(&block_hash)?
+                            ));
+                        }
                     }
+                    self.store_l2_block(l2_block, start).await?;
                 }
-            } else {
-                let (txs_len, logs_len) = self.indexer.store_l2_block(l2_block).await?;
-
-                let duration = start.elapsed();
-                log::info!(
-                    "Sync block {}, {} txs, {} logs, duration: {:?}",
-                    current_block_number,
-                    txs_len,
-                    logs_len,
-                    duration,
-                );
-                self.bump_tip().await?;
             }
 
             return Ok(true);
@@ -174,25 +424,179 @@ impl Runner {
         Ok(false)
     }
 
+    async fn store_l2_block(&mut self, l2_block: L2Block, start: Instant) -> Result<()> {
+        let block_number: u64 = l2_block.raw().number().unpack();
+        let (txs_counts, logs_counts) = self.indexer.store_l2_block(l2_block).await?;
+
+        let duration = start.elapsed();
+        log::info!(
+            "Sync block {}, txs: {}, logs: {}, duration: {:?}",
+            block_number,
+            txs_counts,
+            logs_counts,
+            duration,
+        );
+        // Advance the cursor by the stored block only, the sync state may have been moved
+        // past a gap by a concurrent backfill
+        self.local_tip = Some(block_number);
+        Ok(())
+    }
+
+    /// Index blocks in [from_block_number, to_block_number] and return, regardless of the local
+    /// tip. Blocks that already exist are re-indexed over the stored rows, so a range can be
+    /// backfilled repeatedly and stale rows are rebuilt. Each block is upserted in its own db
+    /// transaction and the sync state is left to the live indexer, so a backfill runs next to
+    /// it without the writer lock.
+    pub async fn backfill(&mut self, from_block_number: u64, to_block_number: u64) -> Result<()> {
+        if from_block_number > to_block_number {
+            return Err(anyhow!(
+                "invalid backfill range: {}..={}",
+                from_block_number,
+                to_block_number
+            ));
+        }
+        // Pruning never comes back for blocks below the watermark
+        if let Some(pruned_below) = self.storage.pruned_below().await? {
+            if from_block_number < pruned_below {
+                return Err(anyhow!(
+                    "logs and transaction inputs of blocks below {} are pruned, backfill from {} or above",
+                    pruned_below,
+                    pruned_below
+                ));
+            }
+        }
+
+        log::info!(
+            "Backfill blocks {}..={}",
+            from_block_number,
+            to_block_number
+        );
+        self.prefetcher.set_last_block_number(Some(to_block_number));
+        for block_number in from_block_number..=to_block_number {
+            if self.is_shutdown() {
+                self.stop_backfill(from_block_number, block_number);
+                return Ok(());
+            }
+            let start = Instant::now();
+            let mut retries = 0;
+            let counts = loop {
+                match self.backfill_block(block_number).await {
+                    Ok(counts) => break Some(counts),
+                    Err(err) => {
+                        let name = format!("Backfill block {}", block_number);
+                        self.retry_policy.backoff(&name, err, &mut retries).await?;
+                        // The backoff ends early on shutdown
+                        if self.is_shutdown() {
+                            break None;
+                        }
+                    }
+                }
+            };
+            let (txs_counts, logs_counts) = match counts {
+                Some(counts) => counts,
+                None => {
+                    self.stop_backfill(from_block_number, block_number);
+                    return Ok(());
+                }
+            };
+
+            let duration = start.elapsed();
+            log::info!(
+                "Backfill block {}, txs: {}, logs: {}, duration: {:?}",
+                block_number,
+                txs_counts,
+                logs_counts,
+                duration,
+            );
+        }
+        self.prefetcher.set_last_block_number(None);
+        self.prefetcher.reset();
+        log::info!(
+            "Backfill blocks {}..={} done",
+            from_block_number,
+            to_block_number
+        );
+
+        Ok(())
+    }
+
+    // `block_number` is the first block not backfilled
+    fn stop_backfill(&mut self, from_block_number: u64, block_number: u64) {
+        self.prefetcher.set_last_block_number(None);
+        self.prefetcher.reset();
+        if block_number > from_block_number {
+            log::info!("Stopped at block {}", block_number - 1);
+        } else {
+            log::info!("Stopped before backfilling any block");
+        }
+    }
+
+    async fn backfill_block(&mut self, block_number: u64) -> Result<(UpsertCounts, UpsertCounts)> {
+        let l2_block = self
+            .prefetcher
+            .fetch(block_number)
+            .await?
+            .ok_or_else(|| anyhow!("block {} not found", block_number))?;
+        self.indexer.backfill_l2_block(l2_block).await
+    }
+
     pub async fn run(&mut self) -> Result<()> {
+        if let Err(err) = self.acquire_writer_lock().await {
+            if self.is_shutdown() {
+                self.log_stopped().await;
+                return Ok(());
+            }
+            return Err(err);
+        }
+        // Only the writer updates finality, the task ends with the process
+        if let Some(interval) = self.finality_check_interval {
+            let tracker = FinalityTracker::new(
+                Arc::clone(&self.godwoken_async_client),
+                require_pg_pool(self.storage.as_ref(), "finality tracking")?.clone(),
+                interval,
+            );
+            smol::spawn(tracker.run()).detach();
+        }
+        let tip_poller = self
+            .tip_poll_interval
+            .map(|interval| TipPoller::spawn(Arc::clone(&self.godwoken_async_client), interval));
+        // Retries of the block being indexed
+        let mut retries = 0;
         loop {
+            if self.is_shutdown() {
+                self.prefetcher.reset();
+                self.log_stopped().await;
+                return Ok(());
+            }
+            if let Err(err) = self.prune_orphans().await {
+                log::warn!("Prune orphans failed: {}", err);
+            }
+            if let Err(err) = self.prune_history().await {
+                log::warn!("Prune history failed: {}", err);
+            }
+            // Tips noticed before this fetch are covered by it
+            if let Some(poller) = &tip_poller {
+                poller.clear();
+            }
             match self.insert().await {
                 Ok(result) => {
+                    retries = 0;
                     if !result {
-                        let sleep_time = std::time::Duration::from_secs(1);
-                        smol::Timer::after(sleep_time).await;
+                        // Wake up on a new tip, poll for the next block anyway
+                        match &tip_poller {
+                            Some(poller) => {
+                                poller.wait(self.block_poll_interval).await;
+                            }
+                            None => {
+                                smol::Timer::after(self.block_poll_interval).await;
+                            }
+                        }
                     }
                 }
                 Err(err) => {
-                    let err_ref = err.downcast_ref::<RpcClientError>();
-                    if let Some(RpcClientError::ConnectionError(_, _)) = err_ref {
-                        log::error!("{}", err);
-                        // wait for 1s
-                        let sleep_time = std::time::Duration::from_secs(1);
-                        smol::Timer::after(sleep_time).await;
-                        continue;
-                    };
-                    return Err(err);
+                    self.retry_policy
+                        .backoff_unlimited("Index block", err, &mut retries)
+                        .await?;
                 }
             };
         }
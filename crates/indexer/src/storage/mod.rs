@@ -0,0 +1,181 @@
+//! Where indexed blocks are written.
+//!
+//! Postgres is the production backend. SQLite, behind the `sqlite` feature, runs the
+//! indexer locally and in tests without a Postgres server. Postgres only features,
+//! the writer lock, finality tracking and verification, are not available with it.
+
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use std::{fmt, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use ckb_types::H256;
+use sqlx::{
+    types::chrono::{DateTime, Utc},
+    PgPool,
+};
+
+use crate::{
+    config::IndexerConfig,
+    insert_l2_block::UpsertCounts,
+    lock::WriterFence,
+    migration::check_schema_version,
+    pool::build_pool,
+    types::{Block, TransactionWithLogs, Withdrawal},
+};
+
+pub use postgres::PgStorage;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+
+/// Rows removed from the indexed tables, or from the orphan tables when pruning.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DeletedRows {
+    pub blocks: u64,
+    pub transactions: u64,
+    pub logs: u64,
+    pub withdrawals: u64,
+}
+
+impl fmt::Display for DeletedRows {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} blocks, {} transactions, {} logs, {} withdrawals",
+            self.blocks, self.transactions, self.logs, self.withdrawals
+        )
+    }
+}
+
+/// Rows compacted by history pruning.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PrunedRows {
+    pub logs: u64,
+    // Transactions whose input was cleared
+    pub inputs: u64,
+}
+
+impl fmt::Display for PrunedRows {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} logs, {} inputs", self.logs, self.inputs)
+    }
+}
+
+/// How writing a block moves the sync state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncStateUpdate {
+    // Move the tip to the block unless a higher one is indexed
+    Advance,
+    // Leave the tip alone, a backfill above the tip must not hide the gap below it
+    Keep,
+}
+
+/// Progress of the indexer, the `sync_state` row written with every block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncState {
+    pub tip_number: u64,
+    pub tip_hash: H256,
+    pub updated_at: DateTime<Utc>,
+    // Unknown for a row seeded by the migration until the next block is written
+    pub indexer_version: Option<String>,
+    pub chain_id: Option<u64>,
+}
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// None if nothing is indexed.
+    async fn sync_state(&self) -> Result<Option<SyncState>>;
+
+    /// Highest indexed block number, None if nothing is indexed.
+    async fn tip_number(&self) -> Result<Option<u64>> {
+        Ok(self.sync_state().await?.map(|s| s.tip_number))
+    }
+
+    async fn block_hash(&self, block_number: u64) -> Result<Option<H256>>;
+
+    /// Upsert a block with its transactions, logs and withdrawals atomically, rows the
+    /// block doesn't have anymore are removed and another block at the same number is
+    /// moved to the orphan tables, replaced by this one. The sync state is updated as
+    /// given. Returns the transaction and log counts.
+    async fn insert_block(
+        &self,
+        block: Block,
+        txs: Vec<TransactionWithLogs>,
+        withdrawals: Vec<Withdrawal>,
+        sync_state: SyncStateUpdate,
+    ) -> Result<(UpsertCounts, UpsertCounts)>;
+
+    /// Move blocks whose number >= `from_block_number` with their transactions, logs and
+    /// withdrawals to the orphan tables atomically, the sync state moves back to the highest
+    /// remaining block.
+    async fn revert_blocks_from(&self, from_block_number: u64) -> Result<DeletedRows>;
+
+    /// Delete orphaned rows reverted before the given time.
+    async fn prune_orphans(&self, reverted_before: DateTime<Utc>) -> Result<DeletedRows>;
+
+    /// Logs and transaction inputs of blocks below this number are pruned, None if
+    /// nothing is pruned.
+    async fn pruned_below(&self) -> Result<Option<u64>>;
+
+    /// Delete the logs and clear the transaction inputs of blocks in
+    /// [from_block_number, below_block_number), then move the pruned below watermark to
+    /// `below_block_number` in the same db transaction.
+    async fn prune_history(
+        &self,
+        from_block_number: u64,
+        below_block_number: u64,
+    ) -> Result<PrunedRows>;
+
+    /// Every following write commits only while the fenced writer lock is held, None stops
+    /// checking. Ignored by storages without the writer lock.
+    fn set_writer_fence(&self, _fence: Option<WriterFence>) {}
+
+    /// The underlying Postgres pool, for the features only Postgres supports.
+    fn pg_pool(&self) -> Option<&PgPool> {
+        None
+    }
+}
+
+// Blocks reverted by a rollback or replaced when indexing a block
+pub(crate) enum Reverted<'a> {
+    // Every block from this number
+    From(u64),
+    // The block at this number unless it has this hash
+    Replaced(u64, &'a [u8]),
+}
+
+/// Open the storage configured by `sqlite_url` or `pg_url`, the Postgres schema must be
+/// up to date.
+pub async fn open_storage(config: &IndexerConfig) -> Result<Arc<dyn Storage>> {
+    let chain_id = config.chain_spec()?.chain_id;
+    if let Some(sqlite_url) = &config.sqlite_url {
+        return open_sqlite(sqlite_url, chain_id).await;
+    }
+    let pool = build_pool(config)?;
+    check_schema_version(&pool).await?;
+    Ok(Arc::new(PgStorage::new(pool, config.insert_mode, chain_id)))
+}
+
+#[cfg(feature = "sqlite")]
+async fn open_sqlite(sqlite_url: &str, chain_id: u64) -> Result<Arc<dyn Storage>> {
+    Ok(Arc::new(
+        SqliteStorage::connect(sqlite_url, chain_id).await?,
+    ))
+}
+
+#[cfg(not(feature = "sqlite"))]
+async fn open_sqlite(_sqlite_url: &str, _chain_id: u64) -> Result<Arc<dyn Storage>> {
+    Err(anyhow!(
+        "\"sqlite_url\" requires gw-web3-indexer built with the sqlite feature"
+    ))
+}
+
+/// Postgres pool of the storage, errors for other backends.
+pub fn require_pg_pool<'a>(storage: &'a dyn Storage, feature: &str) -> Result<&'a PgPool> {
+    storage
+        .pg_pool()
+        .ok_or_else(|| anyhow!("{} requires the Postgres storage", feature))
+}
@@ -0,0 +1,354 @@
+use std::{convert::TryFrom, sync::RwLock};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use ckb_types::H256;
+use itertools::Itertools;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use sqlx::{
+    types::chrono::{DateTime, Utc},
+    PgPool, Postgres,
+};
+
+use super::{DeletedRows, PrunedRows, Reverted, Storage, SyncState, SyncStateUpdate};
+use crate::{
+    insert_l2_block::{
+        insert_web3_block, insert_web3_txs_and_logs, insert_web3_withdrawals, remove_surplus_rows,
+        InsertMode, UpsertCounts,
+    },
+    lock::WriterFence,
+    types::{Block, TransactionWithLogs, Withdrawal},
+    VERSION,
+};
+
+// Transactions upserted by one statement, 19 bind parameters each in values mode
+const INSERT_TXS_BATCH_SIZE: usize = 1000;
+
+// Columns copied to the orphan tables
+const BLOCK_COLUMNS: &str =
+    r#"number, hash, parent_hash, gas_limit, gas_used, miner, size, "timestamp", finality_status"#;
+const TX_COLUMNS: &str = "id, hash, eth_tx_hash, block_number, block_hash, transaction_index, from_address, to_address, value, nonce, gas_limit, gas_price, input, v, r, s, cumulative_gas_used, gas_used, contract_address, exit_code";
+const LOG_COLUMNS: &str = "id, transaction_id, transaction_hash, transaction_index, block_number, block_hash, address, data, log_index, topics";
+const WITHDRAWAL_COLUMNS: &str = "id, block_number, block_hash, withdrawal_index, account_script_hash, registry_id, address, amount, capacity, udt_script_hash, owner_lock_hash, nonce";
+
+pub struct PgStorage {
+    pool: PgPool,
+    insert_mode: InsertMode,
+    // Recorded in sync_state
+    chain_id: u64,
+    // Checked by write transactions before committing
+    writer_fence: RwLock<Option<WriterFence>>,
+}
+
+impl PgStorage {
+    pub fn new(pool: PgPool, insert_mode: InsertMode, chain_id: u64) -> Self {
+        PgStorage {
+            pool,
+            insert_mode,
+            chain_id,
+            writer_fence: RwLock::new(None),
+        }
+    }
+
+    async fn check_writer_fence(&self, pg_tx: &mut sqlx::Transaction<'_, Postgres>) -> Result<()> {
+        let fence = *self.writer_fence.read().expect("writer fence poisoned");
+        match fence {
+            Some(fence) => fence.check(pg_tx).await,
+            None => Ok(()),
+        }
+    }
+
+    // Move the tip to the given block unless a higher block is indexed
+    async fn advance_sync_state(
+        &self,
+        block_number: u64,
+        block_hash: &[u8],
+        pg_tx: &mut sqlx::Transaction<'_, Postgres>,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO sync_state (id, tip_number, tip_hash, updated_at, indexer_version, chain_id) VALUES (1, $1, $2, now(), $3, $4)
+            ON CONFLICT (id) DO UPDATE SET tip_number = EXCLUDED.tip_number, tip_hash = EXCLUDED.tip_hash, updated_at = EXCLUDED.updated_at, indexer_version = EXCLUDED.indexer_version, chain_id = EXCLUDED.chain_id
+            WHERE sync_state.tip_number <= EXCLUDED.tip_number",
+        )
+        .bind(Decimal::from(block_number))
+        .bind(block_hash)
+        .bind(VERSION)
+        .bind(i64::try_from(self.chain_id)?)
+        .execute(pg_tx)
+        .await?;
+        Ok(())
+    }
+
+    // Point the tip at the highest indexed block, none left removes the row
+    async fn reset_sync_state(&self, pg_tx: &mut sqlx::Transaction<'_, Postgres>) -> Result<()> {
+        sqlx::query("DELETE FROM sync_state")
+            .execute(&mut *pg_tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO sync_state (id, tip_number, tip_hash, updated_at, indexer_version, chain_id)
+            SELECT 1, number, hash, now(), $1, $2 FROM blocks ORDER BY number DESC LIMIT 1",
+        )
+        .bind(VERSION)
+        .bind(i64::try_from(self.chain_id)?)
+        .execute(&mut *pg_tx)
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for PgStorage {
+    async fn sync_state(&self) -> Result<Option<SyncState>> {
+        type Row = (Decimal, Vec<u8>, DateTime<Utc>, Option<String>, Option<i64>);
+        let row: Option<Row> = sqlx::query_as(
+            "SELECT tip_number, tip_hash, updated_at, indexer_version, chain_id FROM sync_state WHERE id = 1",
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        let (tip_number, tip_hash, updated_at, indexer_version, chain_id) = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        Ok(Some(SyncState {
+            tip_number: tip_number
+                .to_u64()
+                .ok_or_else(|| anyhow!("invalid sync state tip number {}", tip_number))?,
+            tip_hash: H256::from_slice(&tip_hash)?,
+            updated_at,
+            indexer_version,
+            chain_id: chain_id.map(u64::try_from).transpose()?,
+        }))
+    }
+
+    async fn block_hash(&self, block_number: u64) -> Result<Option<H256>> {
+        let row: Option<(Vec<u8>,)> =
+            sqlx::query_as("SELECT hash FROM blocks WHERE number = $1 LIMIT 1")
+                .bind(Decimal::from(block_number))
+                .fetch_optional(&self.pool)
+                .await?;
+        match row {
+            Some((hash,)) => Ok(Some(H256::from_slice(&hash)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn insert_block(
+        &self,
+        block: Block,
+        txs: Vec<TransactionWithLogs>,
+        withdrawals: Vec<Withdrawal>,
+        sync_state: SyncStateUpdate,
+    ) -> Result<(UpsertCounts, UpsertCounts)> {
+        let block_number = block.number;
+        let number = Decimal::from(block_number);
+        let txs_len = txs.len();
+        let txs_slice = txs
+            .into_iter()
+            .chunks(INSERT_TXS_BATCH_SIZE)
+            .into_iter()
+            .map(|chunk| chunk.collect())
+            .collect::<Vec<Vec<_>>>();
+        let mut pg_tx = self.pool.begin().await?;
+
+        // Rows of another block at this number may hold the same transaction hashes at
+        // other positions, archive them before upserting
+        let block_hash = block.hash.as_slice().to_vec();
+        let archived =
+            archive_blocks(Reverted::Replaced(block_number, &block_hash), &mut pg_tx).await?;
+        if archived != DeletedRows::default() {
+            log::info!("Archived replaced block {}: {}", block_number, archived);
+        }
+
+        let mut txs_counts = UpsertCounts::default();
+        let mut logs_counts = UpsertCounts::default();
+        let mut log_keys = vec![];
+        for txs in txs_slice {
+            let (txs_part_counts, logs_part_counts, mut log_part_keys) =
+                insert_web3_txs_and_logs(txs, self.insert_mode, &mut pg_tx).await?;
+            txs_counts += txs_part_counts;
+            logs_counts += logs_part_counts;
+            log_keys.append(&mut log_part_keys);
+        }
+
+        // Drop transactions and logs the block doesn't have anymore
+        let (removed_txs, removed_logs) =
+            remove_surplus_rows(block_number, txs_len, log_keys, &mut pg_tx).await?;
+        if removed_txs > 0 || removed_logs > 0 {
+            log::info!(
+                "Removed {} txs, {} logs no longer in block {}",
+                removed_txs,
+                removed_logs,
+                block_number
+            );
+        }
+
+        insert_web3_withdrawals(block_number, withdrawals, &mut pg_tx).await?;
+        insert_web3_block(block, &mut pg_tx).await?;
+        sqlx::query(
+            "UPDATE orphan_blocks SET replaced_by_hash = $2 WHERE number = $1 AND hash <> $2 AND replaced_by_hash IS NULL",
+        )
+        .bind(number)
+        .bind(&block_hash)
+        .execute(&mut pg_tx)
+        .await?;
+        if sync_state == SyncStateUpdate::Advance {
+            self.advance_sync_state(block_number, &block_hash, &mut pg_tx)
+                .await?;
+        }
+        self.check_writer_fence(&mut pg_tx).await?;
+        pg_tx.commit().await?;
+
+        Ok((txs_counts, logs_counts))
+    }
+
+    async fn revert_blocks_from(&self, from_block_number: u64) -> Result<DeletedRows> {
+        let mut tx = self.pool.begin().await?;
+        let reverted = archive_blocks(Reverted::From(from_block_number), &mut tx).await?;
+        self.reset_sync_state(&mut tx).await?;
+        self.check_writer_fence(&mut tx).await?;
+        tx.commit().await?;
+        Ok(reverted)
+    }
+
+    async fn prune_orphans(&self, reverted_before: DateTime<Utc>) -> Result<DeletedRows> {
+        let mut tx = self.pool.begin().await?;
+        let withdrawals = sqlx::query("DELETE FROM orphan_withdrawals WHERE reverted_at < $1")
+            .bind(reverted_before)
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        let logs = sqlx::query("DELETE FROM orphan_logs WHERE reverted_at < $1")
+            .bind(reverted_before)
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        let transactions = sqlx::query("DELETE FROM orphan_transactions WHERE reverted_at < $1")
+            .bind(reverted_before)
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        let blocks = sqlx::query("DELETE FROM orphan_blocks WHERE reverted_at < $1")
+            .bind(reverted_before)
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        self.check_writer_fence(&mut tx).await?;
+        tx.commit().await?;
+        Ok(DeletedRows {
+            blocks,
+            transactions,
+            logs,
+            withdrawals,
+        })
+    }
+
+    async fn pruned_below(&self) -> Result<Option<u64>> {
+        let row: Option<(Decimal,)> =
+            sqlx::query_as("SELECT pruned_below FROM prune_state WHERE id = 1")
+                .fetch_optional(&self.pool)
+                .await?;
+        row.map(|(n,)| {
+            n.to_u64()
+                .ok_or_else(|| anyhow!("invalid pruned below block number {}", n))
+        })
+        .transpose()
+    }
+
+    async fn prune_history(
+        &self,
+        from_block_number: u64,
+        below_block_number: u64,
+    ) -> Result<PrunedRows> {
+        let from = Decimal::from(from_block_number);
+        let below = Decimal::from(below_block_number);
+        let mut tx = self.pool.begin().await?;
+        let logs = sqlx::query("DELETE FROM logs WHERE block_number >= $1 AND block_number < $2")
+            .bind(from)
+            .bind(below)
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        let inputs = sqlx::query(
+            "UPDATE transactions SET input = NULL WHERE block_number >= $1 AND block_number < $2 AND input IS NOT NULL",
+        )
+        .bind(from)
+        .bind(below)
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+        sqlx::query(
+            "INSERT INTO prune_state (id, pruned_below, updated_at) VALUES (1, $1, now())
+            ON CONFLICT (id) DO UPDATE SET pruned_below = EXCLUDED.pruned_below, updated_at = EXCLUDED.updated_at
+            WHERE prune_state.pruned_below < EXCLUDED.pruned_below",
+        )
+        .bind(below)
+        .execute(&mut tx)
+        .await?;
+        self.check_writer_fence(&mut tx).await?;
+        tx.commit().await?;
+        Ok(PrunedRows { logs, inputs })
+    }
+
+    fn set_writer_fence(&self, fence: Option<WriterFence>) {
+        *self.writer_fence.write().expect("writer fence poisoned") = fence;
+    }
+
+    fn pg_pool(&self) -> Option<&PgPool> {
+        Some(&self.pool)
+    }
+}
+
+// Move reverted blocks with their transactions, logs and withdrawals to the orphan tables
+async fn archive_blocks(
+    reverted: Reverted<'_>,
+    pg_tx: &mut sqlx::Transaction<'_, Postgres>,
+) -> Result<DeletedRows> {
+    let (block_filter, row_filter) = match reverted {
+        Reverted::From(_) => ("number >= $1", "block_number >= $1"),
+        Reverted::Replaced(_, _) => (
+            "number = $1 AND hash <> $2",
+            "block_number = $1 AND block_hash <> $2",
+        ),
+    };
+    let withdrawals = move_to_orphans(
+        "withdrawals",
+        WITHDRAWAL_COLUMNS,
+        row_filter,
+        &reverted,
+        pg_tx,
+    )
+    .await?;
+    let logs = move_to_orphans("logs", LOG_COLUMNS, row_filter, &reverted, pg_tx).await?;
+    let transactions =
+        move_to_orphans("transactions", TX_COLUMNS, row_filter, &reverted, pg_tx).await?;
+    let blocks = move_to_orphans("blocks", BLOCK_COLUMNS, block_filter, &reverted, pg_tx).await?;
+    Ok(DeletedRows {
+        blocks,
+        transactions,
+        logs,
+        withdrawals,
+    })
+}
+
+async fn move_to_orphans(
+    table: &str,
+    columns: &str,
+    filter: &str,
+    reverted: &Reverted<'_>,
+    pg_tx: &mut sqlx::Transaction<'_, Postgres>,
+) -> Result<u64> {
+    let sql = format!(
+        "WITH reverted AS (DELETE FROM {table} WHERE {filter} RETURNING {columns})
+        INSERT INTO orphan_{table} ({columns}, reverted_at) SELECT {columns}, now() FROM reverted",
+        table = table,
+        columns = columns,
+        filter = filter,
+    );
+    let query = match reverted {
+        Reverted::From(number) => sqlx::query(&sql).bind(Decimal::from(*number)),
+        Reverted::Replaced(number, hash) => {
+            sqlx::query(&sql).bind(Decimal::from(*number)).bind(*hash)
+        }
+    };
+    Ok(query.execute(&mut *pg_tx).await?.rows_affected())
+}
@@ -0,0 +1,950 @@
+use std::{collections::HashSet, convert::TryFrom, str::FromStr};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use ckb_types::H256;
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    types::chrono::{DateTime, Utc},
+    Sqlite, SqlitePool,
+};
+
+use super::{DeletedRows, PrunedRows, Reverted, Storage, SyncState, SyncStateUpdate};
+use crate::{
+    insert_l2_block::UpsertCounts,
+    types::{Block, Log, Transaction, TransactionWithLogs, Withdrawal},
+    VERSION,
+};
+
+// Same tables as Postgres, numbers which may not fit in an i64 are stored as decimal
+// text and the topics of a log are concatenated into one blob
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS blocks (
+        number INTEGER PRIMARY KEY,
+        hash BLOB NOT NULL UNIQUE,
+        parent_hash BLOB NOT NULL,
+        gas_limit TEXT NOT NULL,
+        gas_used TEXT NOT NULL,
+        miner BLOB NOT NULL,
+        size INTEGER NOT NULL,
+        timestamp TEXT NOT NULL,
+        finality_status TEXT NOT NULL DEFAULT 'unfinalized'
+    )",
+    "CREATE TABLE IF NOT EXISTS transactions (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        hash BLOB NOT NULL UNIQUE,
+        eth_tx_hash BLOB NOT NULL UNIQUE,
+        block_number INTEGER NOT NULL,
+        block_hash BLOB NOT NULL,
+        transaction_index INTEGER NOT NULL,
+        from_address BLOB NOT NULL,
+        to_address BLOB,
+        value TEXT NOT NULL,
+        nonce INTEGER NOT NULL,
+        gas_limit TEXT,
+        gas_price TEXT,
+        input BLOB,
+        v INTEGER NOT NULL,
+        r BLOB NOT NULL,
+        s BLOB NOT NULL,
+        cumulative_gas_used TEXT,
+        gas_used TEXT,
+        contract_address BLOB,
+        exit_code INTEGER NOT NULL,
+        UNIQUE (block_number, transaction_index)
+    )",
+    "CREATE TABLE IF NOT EXISTS logs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        transaction_id INTEGER NOT NULL,
+        transaction_hash BLOB NOT NULL,
+        transaction_index INTEGER NOT NULL,
+        block_number INTEGER NOT NULL,
+        block_hash BLOB NOT NULL,
+        address BLOB NOT NULL,
+        data BLOB,
+        log_index INTEGER NOT NULL,
+        topics BLOB NOT NULL,
+        UNIQUE (transaction_id, log_index)
+    )",
+    "CREATE INDEX IF NOT EXISTS logs_block_number_index ON logs (block_number)",
+    "CREATE TABLE IF NOT EXISTS orphan_blocks (
+        orphan_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        number INTEGER NOT NULL,
+        hash BLOB NOT NULL,
+        parent_hash BLOB NOT NULL,
+        gas_limit TEXT NOT NULL,
+        gas_used TEXT NOT NULL,
+        miner BLOB NOT NULL,
+        size INTEGER NOT NULL,
+        timestamp TEXT NOT NULL,
+        finality_status TEXT NOT NULL,
+        reverted_at TEXT NOT NULL,
+        replaced_by_hash BLOB
+    )",
+    "CREATE TABLE IF NOT EXISTS orphan_transactions (
+        orphan_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        id INTEGER NOT NULL,
+        hash BLOB NOT NULL,
+        eth_tx_hash BLOB NOT NULL,
+        block_number INTEGER NOT NULL,
+        block_hash BLOB NOT NULL,
+        transaction_index INTEGER NOT NULL,
+        from_address BLOB NOT NULL,
+        to_address BLOB,
+        value TEXT NOT NULL,
+        nonce INTEGER NOT NULL,
+        gas_limit TEXT,
+        gas_price TEXT,
+        input BLOB,
+        v INTEGER NOT NULL,
+        r BLOB NOT NULL,
+        s BLOB NOT NULL,
+        cumulative_gas_used TEXT,
+        gas_used TEXT,
+        contract_address BLOB,
+        exit_code INTEGER NOT NULL,
+        reverted_at TEXT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS orphan_logs (
+        orphan_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        id INTEGER NOT NULL,
+        transaction_id INTEGER NOT NULL,
+        transaction_hash BLOB NOT NULL,
+        transaction_index INTEGER NOT NULL,
+        block_number INTEGER NOT NULL,
+        block_hash BLOB NOT NULL,
+        address BLOB NOT NULL,
+        data BLOB,
+        log_index INTEGER NOT NULL,
+        topics BLOB NOT NULL,
+        reverted_at TEXT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS withdrawals (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        block_number INTEGER NOT NULL,
+        block_hash BLOB NOT NULL,
+        withdrawal_index INTEGER NOT NULL,
+        account_script_hash BLOB NOT NULL,
+        registry_id INTEGER NOT NULL,
+        address BLOB,
+        amount TEXT NOT NULL,
+        capacity TEXT NOT NULL,
+        udt_script_hash BLOB NOT NULL,
+        owner_lock_hash BLOB NOT NULL,
+        nonce INTEGER NOT NULL,
+        UNIQUE (block_number, withdrawal_index)
+    )",
+    "CREATE TABLE IF NOT EXISTS orphan_withdrawals (
+        orphan_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        id INTEGER NOT NULL,
+        block_number INTEGER NOT NULL,
+        block_hash BLOB NOT NULL,
+        withdrawal_index INTEGER NOT NULL,
+        account_script_hash BLOB NOT NULL,
+        registry_id INTEGER NOT NULL,
+        address BLOB,
+        amount TEXT NOT NULL,
+        capacity TEXT NOT NULL,
+        udt_script_hash BLOB NOT NULL,
+        owner_lock_hash BLOB NOT NULL,
+        nonce INTEGER NOT NULL,
+        reverted_at TEXT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS sync_state (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        tip_number INTEGER NOT NULL,
+        tip_hash BLOB NOT NULL,
+        updated_at TEXT NOT NULL,
+        indexer_version TEXT,
+        chain_id INTEGER
+    )",
+    "CREATE TABLE IF NOT EXISTS prune_state (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        pruned_below INTEGER NOT NULL,
+        updated_at TEXT NOT NULL
+    )",
+];
+
+// Columns copied to the orphan tables
+const BLOCK_COLUMNS: &str =
+    "number, hash, parent_hash, gas_limit, gas_used, miner, size, timestamp, finality_status";
+const TX_COLUMNS: &str = "id, hash, eth_tx_hash, block_number, block_hash, transaction_index, from_address, to_address, value, nonce, gas_limit, gas_price, input, v, r, s, cumulative_gas_used, gas_used, contract_address, exit_code";
+const LOG_COLUMNS: &str = "id, transaction_id, transaction_hash, transaction_index, block_number, block_hash, address, data, log_index, topics";
+const WITHDRAWAL_COLUMNS: &str = "id, block_number, block_hash, withdrawal_index, account_script_hash, registry_id, address, amount, capacity, udt_script_hash, owner_lock_hash, nonce";
+
+/// SQLite storage for local runs and tests, the schema is created on connect.
+pub struct SqliteStorage {
+    pool: SqlitePool,
+    chain_id: u64,
+}
+
+impl SqliteStorage {
+    /// Open the database at `url`, e.g. `sqlite://indexer.db` or `sqlite::memory:`.
+    pub async fn connect(url: &str, chain_id: u64) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        // One connection, every in memory connection is a database of its own
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await?;
+        for statement in SCHEMA {
+            sqlx::query(statement).execute(&pool).await?;
+        }
+        Ok(SqliteStorage { pool, chain_id })
+    }
+
+    // Point the tip at the highest indexed block, none left removes the row
+    async fn reset_sync_state(&self, db_tx: &mut sqlx::Transaction<'_, Sqlite>) -> Result<()> {
+        sqlx::query("DELETE FROM sync_state")
+            .execute(&mut *db_tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO sync_state (id, tip_number, tip_hash, updated_at, indexer_version, chain_id)
+            SELECT 1, number, hash, ?, ?, ? FROM blocks ORDER BY number DESC LIMIT 1",
+        )
+        .bind(Utc::now())
+        .bind(VERSION)
+        .bind(i64::try_from(self.chain_id)?)
+        .execute(&mut *db_tx)
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn sync_state(&self) -> Result<Option<SyncState>> {
+        type Row = (i64, Vec<u8>, DateTime<Utc>, Option<String>, Option<i64>);
+        let row: Option<Row> = sqlx::query_as(
+            "SELECT tip_number, tip_hash, updated_at, indexer_version, chain_id FROM sync_state WHERE id = 1",
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        let (tip_number, tip_hash, updated_at, indexer_version, chain_id) = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        Ok(Some(SyncState {
+            tip_number: u64::try_from(tip_number)?,
+            tip_hash: H256::from_slice(&tip_hash)?,
+            updated_at,
+            indexer_version,
+            chain_id: chain_id.map(u64::try_from).transpose()?,
+        }))
+    }
+
+    async fn block_hash(&self, block_number: u64) -> Result<Option<H256>> {
+        let row: Option<(Vec<u8>,)> = sqlx::query_as("SELECT hash FROM blocks WHERE number = ?")
+            .bind(i64::try_from(block_number)?)
+            .fetch_optional(&self.pool)
+            .await?;
+        match row {
+            Some((hash,)) => Ok(Some(H256::from_slice(&hash)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn insert_block(
+        &self,
+        block: Block,
+        txs: Vec<TransactionWithLogs>,
+        withdrawals: Vec<Withdrawal>,
+        sync_state: SyncStateUpdate,
+    ) -> Result<(UpsertCounts, UpsertCounts)> {
+        let number = i64::try_from(block.number)?;
+        let block_hash = block.hash.as_slice().to_vec();
+        let mut db_tx = self.pool.begin().await?;
+
+        // Rows of another block at this number may hold the same transaction hashes
+        archive_blocks(Reverted::Replaced(block.number, &block_hash), &mut db_tx).await?;
+
+        let mut txs_counts = UpsertCounts::default();
+        let mut logs_counts = UpsertCounts::default();
+        let mut log_ids = HashSet::new();
+        let txs_len = txs.len();
+        for tx_with_logs in txs {
+            let (transaction_id, inserted) = upsert_tx(&tx_with_logs.tx, &mut db_tx).await?;
+            txs_counts.count(inserted);
+            for log in tx_with_logs.logs.iter() {
+                let (log_id, inserted) = upsert_log(transaction_id, log, &mut db_tx).await?;
+                logs_counts.count(inserted);
+                log_ids.insert(log_id);
+            }
+        }
+
+        // Drop transactions and logs the block doesn't have anymore
+        let existing_log_ids: Vec<(i64,)> =
+            sqlx::query_as("SELECT id FROM logs WHERE block_number = ?")
+                .bind(number)
+                .fetch_all(&mut db_tx)
+                .await?;
+        for (id,) in existing_log_ids {
+            if !log_ids.contains(&id) {
+                sqlx::query("DELETE FROM logs WHERE id = ?")
+                    .bind(id)
+                    .execute(&mut db_tx)
+                    .await?;
+            }
+        }
+        sqlx::query("DELETE FROM transactions WHERE block_number = ? AND transaction_index >= ?")
+            .bind(number)
+            .bind(i64::try_from(txs_len)?)
+            .execute(&mut db_tx)
+            .await?;
+
+        sqlx::query("DELETE FROM withdrawals WHERE block_number = ?")
+            .bind(number)
+            .execute(&mut db_tx)
+            .await?;
+        for withdrawal in withdrawals.iter() {
+            insert_withdrawal(withdrawal, &mut db_tx).await?;
+        }
+
+        sqlx::query(
+            "INSERT INTO blocks (number, hash, parent_hash, gas_limit, gas_used, timestamp, miner, size) VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (number) DO UPDATE SET hash = excluded.hash, parent_hash = excluded.parent_hash, gas_limit = excluded.gas_limit, gas_used = excluded.gas_used, timestamp = excluded.timestamp, miner = excluded.miner, size = excluded.size,
+            finality_status = CASE WHEN blocks.hash = excluded.hash THEN blocks.finality_status ELSE 'unfinalized' END",
+        )
+        .bind(number)
+        .bind(&block_hash)
+        .bind(block.parent_hash.as_slice())
+        .bind(block.gas_limit.to_string())
+        .bind(block.gas_used.to_string())
+        .bind(block.timestamp)
+        .bind(&block.miner[..])
+        .bind(i64::try_from(block.size)?)
+        .execute(&mut db_tx)
+        .await?;
+        sqlx::query(
+            "UPDATE orphan_blocks SET replaced_by_hash = ? WHERE number = ? AND hash <> ? AND replaced_by_hash IS NULL",
+        )
+        .bind(&block_hash)
+        .bind(number)
+        .bind(&block_hash)
+        .execute(&mut db_tx)
+        .await?;
+
+        // Move the tip to this block unless a higher block is indexed
+        if sync_state == SyncStateUpdate::Advance {
+            sqlx::query(
+                "INSERT INTO sync_state (id, tip_number, tip_hash, updated_at, indexer_version, chain_id) VALUES (1, ?, ?, ?, ?, ?)
+                ON CONFLICT (id) DO UPDATE SET tip_number = excluded.tip_number, tip_hash = excluded.tip_hash, updated_at = excluded.updated_at, indexer_version = excluded.indexer_version, chain_id = excluded.chain_id
+                WHERE sync_state.tip_number <= excluded.tip_number",
+            )
+            .bind(number)
+            .bind(&block_hash)
+            .bind(Utc::now())
+            .bind(VERSION)
+            .bind(i64::try_from(self.chain_id)?)
+            .execute(&mut db_tx)
+            .await?;
+        }
+
+        db_tx.commit().await?;
+        Ok((txs_counts, logs_counts))
+    }
+
+    async fn revert_blocks_from(&self, from_block_number: u64) -> Result<DeletedRows> {
+        let mut db_tx = self.pool.begin().await?;
+        let reverted = archive_blocks(Reverted::From(from_block_number), &mut db_tx).await?;
+        self.reset_sync_state(&mut db_tx).await?;
+        db_tx.commit().await?;
+        Ok(reverted)
+    }
+
+    async fn prune_orphans(&self, reverted_before: DateTime<Utc>) -> Result<DeletedRows> {
+        let mut db_tx = self.pool.begin().await?;
+        let withdrawals = sqlx::query("DELETE FROM orphan_withdrawals WHERE reverted_at < ?")
+            .bind(reverted_before)
+            .execute(&mut db_tx)
+            .await?
+            .rows_affected();
+        let logs = sqlx::query("DELETE FROM orphan_logs WHERE reverted_at < ?")
+            .bind(reverted_before)
+            .execute(&mut db_tx)
+            .await?
+            .rows_affected();
+        let transactions = sqlx::query("DELETE FROM orphan_transactions WHERE reverted_at < ?")
+            .bind(reverted_before)
+            .execute(&mut db_tx)
+            .await?
+            .rows_affected();
+        let blocks = sqlx::query("DELETE FROM orphan_blocks WHERE reverted_at < ?")
+            .bind(reverted_before)
+            .execute(&mut db_tx)
+            .await?
+            .rows_affected();
+        db_tx.commit().await?;
+        Ok(DeletedRows {
+            blocks,
+            transactions,
+            logs,
+            withdrawals,
+        })
+    }
+
+    async fn pruned_below(&self) -> Result<Option<u64>> {
+        let row: Option<(i64,)> =
+            sqlx::query_as("SELECT pruned_below FROM prune_state WHERE id = 1")
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(row.map(|(n,)| u64::try_from(n)).transpose()?)
+    }
+
+    async fn prune_history(
+        &self,
+        from_block_number: u64,
+        below_block_number: u64,
+    ) -> Result<PrunedRows> {
+        let from = i64::try_from(from_block_number)?;
+        let below = i64::try_from(below_block_number)?;
+        let mut db_tx = self.pool.begin().await?;
+        let logs = sqlx::query("DELETE FROM logs WHERE block_number >= ? AND block_number < ?")
+            .bind(from)
+            .bind(below)
+            .execute(&mut db_tx)
+            .await?
+            .rows_affected();
+        let inputs = sqlx::query(
+            "UPDATE transactions SET input = NULL WHERE block_number >= ? AND block_number < ? AND input IS NOT NULL",
+        )
+        .bind(from)
+        .bind(below)
+        .execute(&mut db_tx)
+        .await?
+        .rows_affected();
+        sqlx::query(
+            "INSERT INTO prune_state (id, pruned_below, updated_at) VALUES (1, ?, ?)
+            ON CONFLICT (id) DO UPDATE SET pruned_below = excluded.pruned_below, updated_at = excluded.updated_at
+            WHERE prune_state.pruned_below < excluded.pruned_below",
+        )
+        .bind(below)
+        .bind(Utc::now())
+        .execute(&mut db_tx)
+        .await?;
+        db_tx.commit().await?;
+        Ok(PrunedRows { logs, inputs })
+    }
+}
+
+// Move reverted blocks with their transactions, logs and withdrawals to the orphan tables
+async fn archive_blocks(
+    reverted: Reverted<'_>,
+    db_tx: &mut sqlx::Transaction<'_, Sqlite>,
+) -> Result<DeletedRows> {
+    let (block_filter, row_filter) = match reverted {
+        Reverted::From(_) => ("number >= ?", "block_number >= ?"),
+        Reverted::Replaced(_, _) => (
+            "number = ? AND hash <> ?",
+            "block_number = ? AND block_hash <> ?",
+        ),
+    };
+    let reverted_at = Utc::now();
+    let withdrawals = move_to_orphans(
+        "withdrawals",
+        WITHDRAWAL_COLUMNS,
+        row_filter,
+        &reverted,
+        reverted_at,
+        db_tx,
+    )
+    .await?;
+    let logs = move_to_orphans(
+        "logs",
+        LOG_COLUMNS,
+        row_filter,
+        &reverted,
+        reverted_at,
+        db_tx,
+    )
+    .await?;
+    let transactions = move_to_orphans(
+        "transactions",
+        TX_COLUMNS,
+        row_filter,
+        &reverted,
+        reverted_at,
+        db_tx,
+    )
+    .await?;
+    let blocks = move_to_orphans(
+        "blocks",
+        BLOCK_COLUMNS,
+        block_filter,
+        &reverted,
+        reverted_at,
+        db_tx,
+    )
+    .await?;
+    Ok(DeletedRows {
+        blocks,
+        transactions,
+        logs,
+        withdrawals,
+    })
+}
+
+async fn move_to_orphans(
+    table: &str,
+    columns: &str,
+    filter: &str,
+    reverted: &Reverted<'_>,
+    reverted_at: DateTime<Utc>,
+    db_tx: &mut sqlx::Transaction<'_, Sqlite>,
+) -> Result<u64> {
+    let insert = format!(
+        "INSERT INTO orphan_{table} ({columns}, reverted_at) SELECT {columns}, ? FROM {table} WHERE {filter}",
+        table = table,
+        columns = columns,
+        filter = filter,
+    );
+    let delete = format!("DELETE FROM {} WHERE {}", table, filter);
+    let (number, hash) = match reverted {
+        Reverted::From(number) => (i64::try_from(*number)?, None),
+        Reverted::Replaced(number, hash) => (i64::try_from(*number)?, Some(*hash)),
+    };
+
+    let mut query = sqlx::query(&insert).bind(reverted_at).bind(number);
+    if let Some(hash) = hash {
+        query = query.bind(hash);
+    }
+    let moved = query.execute(&mut *db_tx).await?.rows_affected();
+    let mut query = sqlx::query(&delete).bind(number);
+    if let Some(hash) = hash {
+        query = query.bind(hash);
+    }
+    query.execute(&mut *db_tx).await?;
+    Ok(moved)
+}
+
+async fn insert_withdrawal(
+    withdrawal: &Withdrawal,
+    db_tx: &mut sqlx::Transaction<'_, Sqlite>,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO withdrawals (block_number, block_hash, withdrawal_index, account_script_hash, registry_id, address, amount, capacity, udt_script_hash, owner_lock_hash, nonce) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(i64::try_from(withdrawal.block_number)?)
+    .bind(withdrawal.block_hash.as_slice())
+    .bind(withdrawal.withdrawal_index)
+    .bind(withdrawal.account_script_hash.as_slice())
+    .bind(withdrawal.registry_id)
+    .bind(withdrawal.address.as_ref().map(|a| &a[..]))
+    .bind(withdrawal.amount.to_string())
+    .bind(withdrawal.capacity.to_string())
+    .bind(withdrawal.udt_script_hash.as_slice())
+    .bind(withdrawal.owner_lock_hash.as_slice())
+    .bind(withdrawal.nonce)
+    .execute(&mut *db_tx)
+    .await?;
+    Ok(())
+}
+
+// Returns the transaction id and whether it was inserted
+async fn upsert_tx(
+    tx: &Transaction,
+    db_tx: &mut sqlx::Transaction<'_, Sqlite>,
+) -> Result<(i64, bool)> {
+    let block_number = i64::try_from(tx.block_number)?;
+    let existing: Option<(i64,)> = sqlx::query_as(
+        "SELECT id FROM transactions WHERE block_number = ? AND transaction_index = ?",
+    )
+    .bind(block_number)
+    .bind(tx.transaction_index)
+    .fetch_optional(&mut *db_tx)
+    .await?;
+
+    let sql = match existing {
+        Some(_) => "UPDATE transactions SET hash = ?, eth_tx_hash = ?, block_number = ?, block_hash = ?, transaction_index = ?, from_address = ?, to_address = ?, value = ?, nonce = ?, gas_limit = ?, gas_price = ?, input = ?, v = ?, r = ?, s = ?, cumulative_gas_used = ?, gas_used = ?, contract_address = ?, exit_code = ? WHERE id = ?",
+        None => "INSERT INTO transactions (hash, eth_tx_hash, block_number, block_hash, transaction_index, from_address, to_address, value, nonce, gas_limit, gas_price, input, v, r, s, cumulative_gas_used, gas_used, contract_address, exit_code) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    };
+    let query = sqlx::query(sql)
+        .bind(tx.gw_tx_hash.as_slice())
+        .bind(tx.compute_eth_tx_hash().as_slice().to_vec())
+        .bind(block_number)
+        .bind(tx.block_hash.as_slice())
+        .bind(tx.transaction_index)
+        .bind(&tx.from_address[..])
+        .bind(tx.to_address.as_ref().map(|a| &a[..]))
+        .bind(tx.value.to_string())
+        .bind(tx.nonce)
+        .bind(tx.gas_limit.to_string())
+        .bind(tx.gas_price.to_string())
+        .bind(&tx.data)
+        .bind(tx.v)
+        .bind(&tx.r[..])
+        .bind(&tx.s[..])
+        .bind(tx.cumulative_gas_used.to_string())
+        .bind(tx.gas_used.to_string())
+        .bind(tx.contract_address.as_ref().map(|a| &a[..]))
+        .bind(tx.exit_code);
+    match existing {
+        Some((id,)) => {
+            query.bind(id).execute(&mut *db_tx).await?;
+            Ok((id, false))
+        }
+        None => {
+            let id = query.execute(&mut *db_tx).await?.last_insert_rowid();
+            Ok((id, true))
+        }
+    }
+}
+
+// Returns the log id and whether it was inserted
+async fn upsert_log(
+    transaction_id: i64,
+    log: &Log,
+    db_tx: &mut sqlx::Transaction<'_, Sqlite>,
+) -> Result<(i64, bool)> {
+    let existing: Option<(i64,)> =
+        sqlx::query_as("SELECT id FROM logs WHERE transaction_id = ? AND log_index = ?")
+            .bind(transaction_id)
+            .bind(log.log_index)
+            .fetch_optional(&mut *db_tx)
+            .await?;
+
+    let sql = match existing {
+        Some(_) => "UPDATE logs SET transaction_id = ?, transaction_hash = ?, transaction_index = ?, block_number = ?, block_hash = ?, address = ?, data = ?, log_index = ?, topics = ? WHERE id = ?",
+        None => "INSERT INTO logs (transaction_id, transaction_hash, transaction_index, block_number, block_hash, address, data, log_index, topics) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    };
+    let topics: Vec<u8> = log
+        .topics
+        .iter()
+        .flat_map(|t| t.as_slice().to_vec())
+        .collect();
+    let query = sqlx::query(sql)
+        .bind(transaction_id)
+        .bind(log.transaction_hash.as_slice())
+        .bind(log.transaction_index)
+        .bind(i64::try_from(log.block_number)?)
+        .bind(log.block_hash.as_slice())
+        .bind(&log.address[..])
+        .bind(&log.data)
+        .bind(log.log_index)
+        .bind(topics);
+    match existing {
+        Some((id,)) => {
+            query.bind(id).execute(&mut *db_tx).await?;
+            Ok((id, false))
+        }
+        None => {
+            let id = query.execute(&mut *db_tx).await?.last_insert_rowid();
+            Ok((id, true))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gw_types::U256;
+    use sqlx::types::chrono::NaiveDateTime;
+
+    use super::*;
+
+    const CHAIN_ID: u64 = 71401;
+
+    fn hash(n: u8, salt: u8) -> gw_common::H256 {
+        let mut h = [0u8; 32];
+        h[0] = n;
+        h[1] = salt;
+        h.into()
+    }
+
+    // Block `number` of fork `salt`, the parent is on the same fork
+    fn block(number: u8, salt: u8) -> Block {
+        Block {
+            number: number.into(),
+            hash: hash(number, salt),
+            parent_hash: hash(number.wrapping_sub(1), salt),
+            gas_limit: 1_000_000,
+            gas_used: 21_000,
+            miner: [number; 20],
+            size: 100,
+            timestamp: DateTime::<Utc>::from_utc(
+                NaiveDateTime::from_timestamp(1_600_000_000 + i64::from(number), 0),
+                Utc,
+            ),
+        }
+    }
+
+    fn tx_with_logs(block: &Block, index: u8, logs: u8) -> TransactionWithLogs {
+        // Unique per block and index, the input makes the eth tx hash unique too
+        let mut tx_hash = [0u8; 32];
+        tx_hash.copy_from_slice(block.hash.as_slice());
+        tx_hash[2] = 1;
+        tx_hash[3] = index;
+        let tx_hash: gw_common::H256 = tx_hash.into();
+        let tx = Transaction::new(
+            tx_hash,
+            Some(CHAIN_ID),
+            block.number,
+            block.hash,
+            index.into(),
+            [1; 20],
+            Some([2; 20]),
+            U256::from(index),
+            index.into(),
+            21_000,
+            1,
+            tx_hash.as_slice().to_vec(),
+            [index; 32],
+            [index; 32],
+            0,
+            21_000,
+            21_000,
+            None,
+            0,
+        );
+        let logs = (0..logs)
+            .map(|log_index| {
+                Log::new(
+                    tx_hash,
+                    index.into(),
+                    block.number,
+                    block.hash,
+                    [3; 20],
+                    vec![log_index],
+                    log_index.into(),
+                    vec![hash(log_index, 1), hash(log_index, 2)],
+                )
+            })
+            .collect();
+        TransactionWithLogs { tx, logs }
+    }
+
+    fn withdrawal(block: &Block, index: u8) -> Withdrawal {
+        Withdrawal {
+            block_number: block.number,
+            block_hash: block.hash,
+            withdrawal_index: index.into(),
+            account_script_hash: hash(index, 3),
+            registry_id: 2,
+            address: Some([index; 20]),
+            amount: u128::MAX,
+            capacity: 40_000_000_000,
+            udt_script_hash: hash(0, 0),
+            owner_lock_hash: hash(index, 4),
+            nonce: index.into(),
+        }
+    }
+
+    async fn storage() -> SqliteStorage {
+        SqliteStorage::connect("sqlite::memory:", CHAIN_ID)
+            .await
+            .unwrap()
+    }
+
+    async fn count(storage: &SqliteStorage, table: &str) -> i64 {
+        let (count,): (i64,) = sqlx::query_as(&format!("SELECT COUNT(*) FROM {}", table))
+            .fetch_one(&storage.pool)
+            .await
+            .unwrap();
+        count
+    }
+
+    #[test]
+    fn insert_block_and_read_back() {
+        smol::block_on(async {
+            let storage = storage().await;
+            assert_eq!(storage.sync_state().await.unwrap(), None);
+
+            let b = block(0, 0);
+            let txs = vec![tx_with_logs(&b, 0, 2), tx_with_logs(&b, 1, 1)];
+            let (txs_counts, logs_counts) = storage
+                .insert_block(
+                    b,
+                    txs,
+                    vec![withdrawal(&block(0, 0), 0)],
+                    SyncStateUpdate::Advance,
+                )
+                .await
+                .unwrap();
+            assert_eq!((txs_counts.new, txs_counts.replaced), (2, 0));
+            assert_eq!((logs_counts.new, logs_counts.replaced), (3, 0));
+
+            let state = storage.sync_state().await.unwrap().unwrap();
+            assert_eq!(state.tip_number, 0);
+            assert_eq!(state.tip_hash.as_bytes(), hash(0, 0).as_slice());
+            assert_eq!(state.chain_id, Some(CHAIN_ID));
+            assert_eq!(
+                storage.block_hash(0).await.unwrap().unwrap().as_bytes(),
+                hash(0, 0).as_slice()
+            );
+            assert_eq!(storage.block_hash(1).await.unwrap(), None);
+            assert_eq!(count(&storage, "withdrawals").await, 1);
+
+            let (amount,): (String,) = sqlx::query_as("SELECT amount FROM withdrawals")
+                .fetch_one(&storage.pool)
+                .await
+                .unwrap();
+            assert_eq!(amount, u128::MAX.to_string());
+        });
+    }
+
+    #[test]
+    fn upsert_converges() {
+        smol::block_on(async {
+            let storage = storage().await;
+            let b = block(0, 0);
+            let txs = vec![tx_with_logs(&b, 0, 2), tx_with_logs(&b, 1, 1)];
+            storage
+                .insert_block(
+                    b,
+                    txs,
+                    vec![withdrawal(&block(0, 0), 0)],
+                    SyncStateUpdate::Advance,
+                )
+                .await
+                .unwrap();
+
+            // Same block again, every row is replaced
+            let b = block(0, 0);
+            let txs = vec![tx_with_logs(&b, 0, 2), tx_with_logs(&b, 1, 1)];
+            let (txs_counts, logs_counts) = storage
+                .insert_block(
+                    b,
+                    txs,
+                    vec![withdrawal(&block(0, 0), 0)],
+                    SyncStateUpdate::Advance,
+                )
+                .await
+                .unwrap();
+            assert_eq!((txs_counts.new, txs_counts.replaced), (0, 2));
+            assert_eq!((logs_counts.new, logs_counts.replaced), (0, 3));
+            assert_eq!(count(&storage, "transactions").await, 2);
+            assert_eq!(count(&storage, "logs").await, 3);
+            assert_eq!(count(&storage, "withdrawals").await, 1);
+
+            // Fewer rows, the surplus is removed
+            let b = block(0, 0);
+            let txs = vec![tx_with_logs(&b, 0, 1)];
+            storage
+                .insert_block(b, txs, vec![], SyncStateUpdate::Advance)
+                .await
+                .unwrap();
+            assert_eq!(count(&storage, "transactions").await, 1);
+            assert_eq!(count(&storage, "logs").await, 1);
+            assert_eq!(count(&storage, "withdrawals").await, 0);
+            assert_eq!(count(&storage, "orphan_blocks").await, 0);
+        });
+    }
+
+    #[test]
+    fn replaced_block_is_archived() {
+        smol::block_on(async {
+            let storage = storage().await;
+            let b = block(0, 0);
+            let txs = vec![tx_with_logs(&b, 0, 2)];
+            storage
+                .insert_block(
+                    b,
+                    txs,
+                    vec![withdrawal(&block(0, 0), 0)],
+                    SyncStateUpdate::Advance,
+                )
+                .await
+                .unwrap();
+
+            let b = block(0, 1);
+            let txs = vec![tx_with_logs(&b, 0, 1)];
+            storage
+                .insert_block(b, txs, vec![], SyncStateUpdate::Advance)
+                .await
+                .unwrap();
+
+            assert_eq!(count(&storage, "blocks").await, 1);
+            assert_eq!(count(&storage, "transactions").await, 1);
+            assert_eq!(count(&storage, "logs").await, 1);
+            assert_eq!(count(&storage, "orphan_blocks").await, 1);
+            assert_eq!(count(&storage, "orphan_transactions").await, 1);
+            assert_eq!(count(&storage, "orphan_logs").await, 2);
+            assert_eq!(count(&storage, "orphan_withdrawals").await, 1);
+            let (orphan_hash, replaced_by_hash): (Vec<u8>, Option<Vec<u8>>) =
+                sqlx::query_as("SELECT hash, replaced_by_hash FROM orphan_blocks")
+                    .fetch_one(&storage.pool)
+                    .await
+                    .unwrap();
+            assert_eq!(orphan_hash, hash(0, 0).as_slice());
+            assert_eq!(replaced_by_hash.unwrap(), hash(0, 1).as_slice());
+        });
+    }
+
+    #[test]
+    fn backfill_keeps_sync_state() {
+        smol::block_on(async {
+            let storage = storage().await;
+            let b = block(0, 0);
+            storage
+                .insert_block(b, vec![], vec![], SyncStateUpdate::Advance)
+                .await
+                .unwrap();
+
+            // A block above the gap is stored without moving the tip
+            let b = block(5, 0);
+            let txs = vec![tx_with_logs(&b, 0, 1)];
+            storage
+                .insert_block(b, txs, vec![], SyncStateUpdate::Keep)
+                .await
+                .unwrap();
+            assert_eq!(storage.tip_number().await.unwrap(), Some(0));
+            assert_eq!(
+                storage.block_hash(5).await.unwrap().unwrap().as_bytes(),
+                hash(5, 0).as_slice()
+            );
+        });
+    }
+
+    #[test]
+    fn revert_and_reinsert() {
+        smol::block_on(async {
+            let storage = storage().await;
+            for number in 0..3 {
+                let b = block(number, 0);
+                let txs = vec![tx_with_logs(&b, 0, 1)];
+                storage
+                    .insert_block(
+                        b,
+                        txs,
+                        vec![withdrawal(&block(number, 0), 0)],
+                        SyncStateUpdate::Advance,
+                    )
+                    .await
+                    .unwrap();
+            }
+            assert_eq!(storage.tip_number().await.unwrap(), Some(2));
+
+            let reverted = storage.revert_blocks_from(1).await.unwrap();
+            assert_eq!(
+                reverted,
+                DeletedRows {
+                    blocks: 2,
+                    transactions: 2,
+                    logs: 2,
+                    withdrawals: 2,
+                }
+            );
+            assert_eq!(storage.tip_number().await.unwrap(), Some(0));
+            assert_eq!(storage.block_hash(1).await.unwrap(), None);
+            assert_eq!(count(&storage, "orphan_blocks").await, 2);
+
+            // The other fork takes over
+            let b = block(1, 1);
+            let txs = vec![tx_with_logs(&b, 0, 1)];
+            storage
+                .insert_block(b, txs, vec![], SyncStateUpdate::Advance)
+                .await
+                .unwrap();
+            let state = storage.sync_state().await.unwrap().unwrap();
+            assert_eq!(state.tip_number, 1);
+            assert_eq!(state.tip_hash.as_bytes(), hash(1, 1).as_slice());
+
+            // Everything reverted, nothing is indexed anymore
+            storage.revert_blocks_from(0).await.unwrap();
+            assert_eq!(storage.sync_state().await.unwrap(), None);
+
+            let pruned = storage.prune_orphans(Utc::now()).await.unwrap();
+            assert_eq!(pruned.blocks, 4);
+            assert_eq!(count(&storage, "orphan_blocks").await, 0);
+        });
+    }
+}
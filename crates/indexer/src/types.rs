@@ -181,3 +181,19 @@ pub struct TransactionWithLogs {
     pub tx: Transaction,
     pub logs: Vec<Log>,
 }
+
+#[derive(Debug)]
+pub struct Withdrawal {
+    pub block_number: u64,
+    pub block_hash: H256,
+    pub withdrawal_index: u32,
+    pub account_script_hash: H256,
+    pub registry_id: u32,
+    // None unless the account is an eth account
+    pub address: Option<Address>,
+    pub amount: u128,
+    pub capacity: u64,
+    pub udt_script_hash: H256,
+    pub owner_lock_hash: H256,
+    pub nonce: u32,
+}
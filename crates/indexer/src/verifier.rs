@@ -0,0 +1,463 @@
+use std::{cmp, collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use ckb_hash::blake2b_256;
+use ckb_types::H256;
+use gw_types::prelude::*;
+use gw_web3_rpc_client::{convertion::to_l2_block, godwoken_async_client::GodwokenAsyncClient};
+use itertools::Itertools;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::{
+    types::chrono::{DateTime, Utc},
+    PgPool,
+};
+
+use crate::{
+    config::IndexerConfig,
+    helper::hex,
+    lock::{try_acquire_writer, WriterLock},
+    prefetch::BlockPrefetcher,
+    retry::RetryPolicy,
+    storage::{require_pg_pool, DeletedRows, Storage},
+    Web3Indexer,
+};
+
+const VERIFY_BATCH_SIZE: u64 = 1000;
+
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    pub from_block_number: u64,
+    pub to_block_number: u64,
+    // Blocks found on chain but not in db
+    pub missing: Vec<u64>,
+    pub mismatches: Vec<BlockMismatch>,
+    pub repaired: Vec<u64>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.mismatches.is_empty()
+    }
+
+    // Block numbers which need to be re-indexed
+    pub fn affected_block_numbers(&self) -> Vec<u64> {
+        self.missing
+            .iter()
+            .copied()
+            .chain(self.mismatches.iter().map(|m| m.block_number))
+            .sorted()
+            .dedup()
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockMismatch {
+    pub block_number: u64,
+    pub field: &'static str,
+    pub db: String,
+    pub chain: String,
+}
+
+struct DbBlockHashes {
+    hash: Vec<u8>,
+    parent_hash: Vec<u8>,
+}
+
+struct DbTransactionHashes {
+    hash: Vec<u8>,
+    block_hash: Vec<u8>,
+}
+
+/// Compare indexed blocks and transactions with the chain, and optionally re-index the
+/// affected blocks.
+pub struct Verifier {
+    indexer: Web3Indexer,
+    godwoken_async_client: Arc<GodwokenAsyncClient>,
+    prefetcher: BlockPrefetcher,
+    pool: PgPool,
+    storage: Arc<dyn Storage>,
+    rollup_type_hash: H256,
+    writer_lock_enabled: bool,
+    // Held while repairing, like the indexer
+    writer_lock: Option<WriterLock>,
+    // Retries of follow rounds
+    retry_policy: RetryPolicy,
+}
+
+impl Verifier {
+    pub fn new(config: IndexerConfig, storage: Arc<dyn Storage>) -> Result<Self> {
+        let pool = require_pg_pool(storage.as_ref(), "verify")?.clone();
+        let godwoken_async_client = Arc::new(GodwokenAsyncClient::with_url(
+            config.godwoken_rpc_url.as_str(),
+        )?);
+        let chain_spec = config.chain_spec()?;
+        let indexer = Web3Indexer::new(
+            chain_spec.l2_sudt_type_script_hash,
+            chain_spec.polyjuice_type_script_hash,
+            chain_spec.rollup_type_hash.clone(),
+            chain_spec.eth_account_lock_hash,
+            Arc::clone(&godwoken_async_client),
+            Arc::clone(&storage),
+        );
+        let prefetcher =
+            BlockPrefetcher::new(Arc::clone(&godwoken_async_client), config.prefetch_window);
+        Ok(Verifier {
+            indexer,
+            godwoken_async_client,
+            prefetcher,
+            pool,
+            storage,
+            rollup_type_hash: chain_spec.rollup_type_hash,
+            writer_lock_enabled: config.writer_lock,
+            writer_lock: None,
+            retry_policy: RetryPolicy::from_config(&config),
+        })
+    }
+
+    // Repairs write blocks, refuse to race the indexer holding the writer lock
+    async fn acquire_writer_lock(&mut self) -> Result<()> {
+        if !self.writer_lock_enabled || self.writer_lock.is_some() {
+            return Ok(());
+        }
+        let lock =
+            try_acquire_writer(self.storage.as_ref(), &self.rollup_type_hash, "repairing").await?;
+        self.writer_lock = Some(lock);
+        Ok(())
+    }
+
+    pub async fn verify(
+        &mut self,
+        from_block_number: u64,
+        to_block_number: u64,
+        repair: bool,
+    ) -> Result<VerifyReport> {
+        if repair {
+            self.acquire_writer_lock().await?;
+        }
+        let mut report = VerifyReport {
+            from_block_number,
+            to_block_number,
+            ..Default::default()
+        };
+
+        self.prefetcher.set_last_block_number(Some(to_block_number));
+        let mut batch_start = from_block_number;
+        while batch_start <= to_block_number {
+            let batch_end = cmp::min(to_block_number, batch_start + VERIFY_BATCH_SIZE - 1);
+            self.verify_batch(batch_start, batch_end, &mut report)
+                .await?;
+
+            log::info!(
+                "Verified blocks {}..={}, missing: {}, mismatches: {}",
+                batch_start,
+                batch_end,
+                report.missing.len(),
+                report.mismatches.len()
+            );
+            batch_start = batch_end + 1;
+        }
+        self.prefetcher.set_last_block_number(None);
+        self.prefetcher.reset();
+
+        if repair {
+            // Pruning never comes back for blocks below the watermark, leave them as they are
+            let pruned_below = self.storage.pruned_below().await?.unwrap_or(0);
+            for block_number in report.affected_block_numbers() {
+                if block_number < pruned_below {
+                    log::warn!(
+                        "Skip repairing block {}, blocks below {} are pruned",
+                        block_number,
+                        pruned_below
+                    );
+                    continue;
+                }
+                if self.repair_block(block_number).await? {
+                    report.repaired.push(block_number);
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    pub async fn tip_number(&self) -> Result<Option<u64>> {
+        self.indexer.tip_number().await
+    }
+
+    /// Keep verifying new blocks as they are indexed, transient errors are retried until they
+    /// go away. Repairing holds the writer lock until exit.
+    pub async fn follow(
+        &mut self,
+        from_block_number: u64,
+        repair: bool,
+        interval: Duration,
+    ) -> Result<()> {
+        if repair {
+            self.acquire_writer_lock().await?;
+        }
+        let mut cursor = from_block_number;
+        // Retries of the current round
+        let mut retries = 0;
+        loop {
+            match self.follow_round(cursor, repair).await {
+                Ok(next_cursor) => {
+                    retries = 0;
+                    cursor = next_cursor;
+                    smol::Timer::after(interval).await;
+                }
+                Err(err) => {
+                    // A failed round may leave prefetched blocks behind
+                    self.prefetcher.set_last_block_number(None);
+                    self.prefetcher.reset();
+                    self.retry_policy
+                        .backoff_unlimited("Verify", err, &mut retries)
+                        .await?;
+                }
+            }
+        }
+    }
+
+    // Verify blocks from the cursor up to the local tip, returns the next cursor
+    async fn follow_round(&mut self, cursor: u64, repair: bool) -> Result<u64> {
+        let tip = match self.indexer.tip_number().await? {
+            Some(tip) if tip >= cursor => tip,
+            _ => return Ok(cursor),
+        };
+        let report = self.verify(cursor, tip, repair).await?;
+        if !report.is_ok() {
+            log::error!(
+                "Verify blocks {}..={}, missing: {:?}, mismatches: {:?}, repaired: {:?}",
+                cursor,
+                tip,
+                report.missing,
+                report.mismatches,
+                report.repaired
+            );
+        }
+        Ok(tip + 1)
+    }
+
+    async fn verify_batch(
+        &mut self,
+        from_block_number: u64,
+        to_block_number: u64,
+        report: &mut VerifyReport,
+    ) -> Result<()> {
+        let db_blocks = get_db_block_hashes(&self.pool, from_block_number, to_block_number).await?;
+        let mut db_txs =
+            get_db_transaction_hashes(&self.pool, from_block_number, to_block_number).await?;
+
+        for block_number in from_block_number..=to_block_number {
+            let db_block = db_blocks.get(&block_number);
+            let l2_block = match self.prefetcher.fetch(block_number).await? {
+                Some(b) => b,
+                None => {
+                    if let Some(db_block) = db_block {
+                        report.mismatches.push(BlockMismatch {
+                            block_number,
+                            field: "hash",
+                            db: hex(&db_block.hash)?,
+                            chain: "null".to_string(),
+                        });
+                    }
+                    continue;
+                }
+            };
+            let db_block = match db_block {
+                Some(b) => b,
+                None => {
+                    report.missing.push(block_number);
+                    continue;
+                }
+            };
+
+            let block_hash = blake2b_256(l2_block.raw().as_slice());
+            if db_block.hash.as_slice() != block_hash {
+                report.mismatches.push(BlockMismatch {
+                    block_number,
+                    field: "hash",
+                    db: hex(&db_block.hash)?,
+                    chain: hex(&block_hash)?,
+                });
+            }
+            let parent_hash = l2_block.raw().parent_block_hash();
+            if db_block.parent_hash.as_slice() != parent_hash.as_slice() {
+                report.mismatches.push(BlockMismatch {
+                    block_number,
+                    field: "parent_hash",
+                    db: hex(&db_block.parent_hash)?,
+                    chain: hex(parent_hash.as_slice())?,
+                });
+            }
+
+            // Only web3 compatible L2 transactions are indexed, the db rows must be exactly
+            // those, in the same order
+            let web3_tx_hashes = self.indexer.web3_transaction_hashes(&l2_block).await?;
+            let txs = db_txs.remove(&block_number).unwrap_or_default();
+            if txs.len() != web3_tx_hashes.len() {
+                report.mismatches.push(BlockMismatch {
+                    block_number,
+                    field: "tx_count",
+                    db: txs.len().to_string(),
+                    chain: web3_tx_hashes.len().to_string(),
+                });
+                continue;
+            }
+            if let Some(tx) = txs.iter().find(|tx| tx.block_hash.as_slice() != block_hash) {
+                report.mismatches.push(BlockMismatch {
+                    block_number,
+                    field: "transactions.block_hash",
+                    db: hex(&tx.block_hash)?,
+                    chain: hex(&block_hash)?,
+                });
+                continue;
+            }
+            let unknown_tx = txs
+                .iter()
+                .zip(web3_tx_hashes.iter())
+                .find(|(tx, web3_tx_hash)| tx.hash.as_slice() != web3_tx_hash.as_slice());
+            if let Some((tx, web3_tx_hash)) = unknown_tx {
+                report.mismatches.push(BlockMismatch {
+                    block_number,
+                    field: "transactions.hash",
+                    db: hex(&tx.hash)?,
+                    chain: hex(web3_tx_hash)?,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // Re-index the block from the chain, or roll back from it if the chain doesn't have it
+    // anymore. Returns whether anything changed
+    async fn repair_block(&self, block_number: u64) -> Result<bool> {
+        match self
+            .godwoken_async_client
+            .get_block_by_number(block_number)
+            .await?
+        {
+            Some(block) => {
+                let l2_block = to_l2_block(block);
+                let (txs_counts, logs_counts) = self.indexer.reindex_l2_block(l2_block).await?;
+                log::info!(
+                    "Repair block {}, txs: {}, logs: {}",
+                    block_number,
+                    txs_counts,
+                    logs_counts,
+                );
+                Ok(true)
+            }
+            None => {
+                // Blocks above the chain tip, e.g. after a reorg to a shorter chain
+                let reverted = self.storage.revert_blocks_from(block_number).await?;
+                if reverted == DeletedRows::default() {
+                    return Ok(false);
+                }
+                log::info!(
+                    "Repair by rolling back blocks from {}, not found on chain, archived: {}",
+                    block_number,
+                    reverted
+                );
+                Ok(true)
+            }
+        }
+    }
+
+    /// Dump a block from the chain side by side with the indexed row.
+    pub async fn inspect_block(&self, block_number: u64) -> Result<serde_json::Value> {
+        let chain_block = self
+            .godwoken_async_client
+            .get_block_by_number(block_number)
+            .await?;
+
+        let row: Option<(Vec<u8>, Vec<u8>, Vec<u8>, i32, DateTime<Utc>)> = sqlx::query_as(
+            "SELECT hash, parent_hash, miner, size, timestamp FROM blocks WHERE number = $1",
+        )
+        .bind(Decimal::from(block_number))
+        .fetch_optional(&self.pool)
+        .await?;
+        let db_block = match row {
+            Some((hash, parent_hash, miner, size, timestamp)) => {
+                let (txs_count,): (i64,) =
+                    sqlx::query_as("SELECT COUNT(*) FROM transactions WHERE block_number = $1")
+                        .bind(Decimal::from(block_number))
+                        .fetch_one(&self.pool)
+                        .await?;
+                let (logs_count,): (i64,) =
+                    sqlx::query_as("SELECT COUNT(*) FROM logs WHERE block_number = $1")
+                        .bind(Decimal::from(block_number))
+                        .fetch_one(&self.pool)
+                        .await?;
+                Some(json!({
+                    "hash": hex(&hash)?,
+                    "parent_hash": hex(&parent_hash)?,
+                    "miner": hex(&miner)?,
+                    "size": size,
+                    "timestamp": timestamp.to_rfc3339(),
+                    "transactions_count": txs_count,
+                    "logs_count": logs_count,
+                }))
+            }
+            None => None,
+        };
+
+        Ok(json!({
+            "number": block_number,
+            "chain": chain_block,
+            "db": db_block,
+        }))
+    }
+}
+
+async fn get_db_block_hashes(
+    pool: &PgPool,
+    from_block_number: u64,
+    to_block_number: u64,
+) -> Result<HashMap<u64, DbBlockHashes>> {
+    let rows: Vec<(Decimal, Vec<u8>, Vec<u8>)> = sqlx::query_as(
+        "SELECT number, hash, parent_hash FROM blocks WHERE number >= $1 AND number <= $2",
+    )
+    .bind(Decimal::from(from_block_number))
+    .bind(Decimal::from(to_block_number))
+    .fetch_all(pool)
+    .await?;
+
+    let blocks = rows
+        .into_iter()
+        .filter_map(|(number, hash, parent_hash)| {
+            number
+                .to_u64()
+                .map(|n| (n, DbBlockHashes { hash, parent_hash }))
+        })
+        .collect();
+    Ok(blocks)
+}
+
+// Transactions of each block, ordered by transaction_index
+async fn get_db_transaction_hashes(
+    pool: &PgPool,
+    from_block_number: u64,
+    to_block_number: u64,
+) -> Result<HashMap<u64, Vec<DbTransactionHashes>>> {
+    let rows: Vec<(Decimal, Vec<u8>, Vec<u8>)> = sqlx::query_as(
+        "SELECT block_number, hash, block_hash FROM transactions WHERE block_number >= $1 AND block_number <= $2 ORDER BY block_number, transaction_index",
+    )
+    .bind(Decimal::from(from_block_number))
+    .bind(Decimal::from(to_block_number))
+    .fetch_all(pool)
+    .await?;
+
+    let mut txs: HashMap<u64, Vec<DbTransactionHashes>> = HashMap::new();
+    for (block_number, hash, block_hash) in rows {
+        if let Some(n) = block_number.to_u64() {
+            txs.entry(n)
+                .or_default()
+                .push(DbTransactionHashes { hash, block_hash });
+        }
+    }
+    Ok(txs)
+}
@@ -5,6 +5,13 @@ pub enum RpcClientError {
     #[error("connection failed by: {0}, error: {1}")]
     ConnectionError(String, anyhow::Error),
 
+    #[error("JSONRPC error by: {method}, code: {code}, message: {message}")]
+    JsonRpcError {
+        method: String,
+        code: i64,
+        message: String,
+    },
+
     #[error(transparent)]
     SerdeJsonError(#[from] serde_json::Error),
 
@@ -2,11 +2,16 @@ use anyhow::Result;
 use async_jsonrpc_client::{BatchTransport, HttpClient, Output, Params as ClientParams, Transport};
 use ckb_jsonrpc_types::Script;
 use ckb_types::H256;
-use gw_jsonrpc_types::ckb_jsonrpc_types::Uint32;
+use gw_jsonrpc_types::{
+    ckb_jsonrpc_types::{Uint32, Uint64},
+    godwoken::{L2BlockView, L2BlockWithStatus, NodeInfo, TxReceipt},
+};
 use itertools::Itertools;
 use serde::de::DeserializeOwned;
 use serde_json::{from_value, json};
 
+use crate::error::RpcClientError;
+
 type AccountID = Uint32;
 
 pub struct GodwokenAsyncClient {
@@ -49,6 +54,62 @@ impl GodwokenAsyncClient {
         Ok(script)
     }
 
+    pub async fn get_tip_block_hash(&self) -> Result<H256> {
+        let tip_block_hash: H256 = self.request("gw_get_tip_block_hash", None).await?;
+
+        Ok(tip_block_hash)
+    }
+
+    pub async fn get_block_hash(&self, block_number: u64) -> Result<Option<H256>> {
+        let block_hash: Option<H256> = self
+            .request(
+                "gw_get_block_hash",
+                Some(ClientParams::Array(vec![json!(Uint64::from(block_number))])),
+            )
+            .await?;
+
+        Ok(block_hash)
+    }
+
+    pub async fn get_block(&self, block_hash: &H256) -> Result<Option<L2BlockWithStatus>> {
+        let block: Option<L2BlockWithStatus> = self
+            .request(
+                "gw_get_block",
+                Some(ClientParams::Array(vec![json!(block_hash)])),
+            )
+            .await?;
+
+        Ok(block)
+    }
+
+    pub async fn get_block_by_number(&self, block_number: u64) -> Result<Option<L2BlockView>> {
+        let block: Option<L2BlockView> = self
+            .request(
+                "gw_get_block_by_number",
+                Some(ClientParams::Array(vec![json!(Uint64::from(block_number))])),
+            )
+            .await?;
+
+        Ok(block)
+    }
+
+    pub async fn get_transaction_receipt(&self, tx_hash: &H256) -> Result<Option<TxReceipt>> {
+        let receipt: Option<TxReceipt> = self
+            .request(
+                "gw_get_transaction_receipt",
+                Some(ClientParams::Array(vec![json!(tx_hash)])),
+            )
+            .await?;
+
+        Ok(receipt)
+    }
+
+    pub async fn get_node_info(&self) -> Result<NodeInfo> {
+        let node_info: NodeInfo = self.request("gw_get_node_info", None).await?;
+
+        Ok(node_info)
+    }
+
     fn client(&self) -> &HttpClient {
         &self.client
     }
@@ -83,9 +144,13 @@ impl GodwokenAsyncClient {
         method: &str,
         params: Option<ClientParams>,
     ) -> Result<T> {
-        let response = self.client().request(method, params).await?;
+        let response = self
+            .client()
+            .request(method, params)
+            .await
+            .map_err(|err| RpcClientError::ConnectionError(method.to_string(), err.into()))?;
         let response_str = response.to_string();
-        match to_result::<T>(response) {
+        match to_result::<T>(method, response) {
             Ok(r) => Ok(r),
             Err(err) => {
                 log::error!(
@@ -103,13 +168,18 @@ impl GodwokenAsyncClient {
         params: Vec<(&str, Option<ClientParams>)>,
     ) -> Result<Vec<T>> {
         let methods = params.iter().map(|p| p.0).unique().collect::<Vec<_>>();
+        let methods_str = format!("{:?}", methods);
 
-        let responses = self.client().request_batch(params).await?;
+        let responses = self
+            .client()
+            .request_batch(params)
+            .await
+            .map_err(|err| RpcClientError::ConnectionError(methods_str.clone(), err.into()))?;
         let responses_str = responses.iter().map(|r| r.to_string()).collect::<Vec<_>>();
 
         let results = responses
             .into_iter()
-            .map(|response| match to_result::<T>(response) {
+            .map(|response| match to_result::<T>(&methods_str, response) {
                 Ok(r) => Ok(r),
                 Err(err) => {
                     log::error!(
@@ -126,9 +196,14 @@ impl GodwokenAsyncClient {
     }
 }
 
-fn to_result<T: DeserializeOwned>(output: Output) -> anyhow::Result<T> {
+fn to_result<T: DeserializeOwned>(method: &str, output: Output) -> anyhow::Result<T> {
     match output {
         Output::Success(success) => Ok(from_value(success.result)?),
-        Output::Failure(failure) => Err(anyhow::anyhow!("JSONRPC error: {}", failure.error)),
+        Output::Failure(failure) => Err(RpcClientError::JsonRpcError {
+            method: method.to_string(),
+            code: failure.error.code.code(),
+            message: failure.error.message,
+        }
+        .into()),
     }
 }
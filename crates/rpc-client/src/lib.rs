@@ -2,3 +2,4 @@ pub mod convertion;
 pub mod error;
 pub mod godwoken_async_client;
 pub mod godwoken_rpc_client;
+pub mod tip_poller;
@@ -0,0 +1,68 @@
+use std::{sync::Arc, time::Duration};
+
+use async_std::channel::{self, Receiver, Sender, TrySendError};
+use ckb_types::H256;
+
+use crate::godwoken_async_client::GodwokenAsyncClient;
+
+/// Poll the tip block hash of Godwoken and notify when it changes.
+///
+/// Godwoken pushes nothing, there is no websocket or long-poll method for new blocks, so
+/// the tip hash is polled every `interval` in a background task, which is much cheaper than
+/// polling for the next block. A new tip is
+/// noticed up to `interval` late. Notifications are coalesced, a waiter only learns that
+/// the tip moved, not how many blocks were produced.
+pub struct TipPoller {
+    receiver: Receiver<H256>,
+}
+
+impl TipPoller {
+    /// Start polling, the background task stops once the poller is dropped.
+    pub fn spawn(godwoken_async_client: Arc<GodwokenAsyncClient>, interval: Duration) -> Self {
+        let (sender, receiver) = channel::bounded(1);
+        async_std::task::spawn(poll(godwoken_async_client, interval, sender));
+        TipPoller { receiver }
+    }
+
+    /// Drop a pending notification, call it before fetching so that `wait` only returns
+    /// for tips which moved after the fetch started.
+    pub fn clear(&self) {
+        while self.receiver.try_recv().is_ok() {}
+    }
+
+    /// Wait for a new tip at most `timeout`, returns the new tip hash if any.
+    pub async fn wait(&self, timeout: Duration) -> Option<H256> {
+        async_std::future::timeout(timeout, self.receiver.recv())
+            .await
+            .ok()
+            .and_then(Result::ok)
+    }
+}
+
+async fn poll(
+    godwoken_async_client: Arc<GodwokenAsyncClient>,
+    interval: Duration,
+    sender: Sender<H256>,
+) {
+    let mut last_tip: Option<H256> = None;
+    loop {
+        match godwoken_async_client.get_tip_block_hash().await {
+            Ok(tip) if Some(&tip) != last_tip.as_ref() => {
+                last_tip = Some(tip.clone());
+                match sender.try_send(tip) {
+                    // A notification is already pending
+                    Ok(()) | Err(TrySendError::Full(_)) => {}
+                    Err(TrySendError::Closed(_)) => return,
+                }
+            }
+            Ok(_) => {}
+            Err(err) => {
+                log::warn!("[tip_poller] Failed to get tip block hash: {}", err);
+            }
+        }
+        if sender.is_closed() {
+            return;
+        }
+        async_std::task::sleep(interval).await;
+    }
+}
@@ -0,0 +1,24 @@
+import { Knex } from "knex";
+
+// One of "unfinalized", "finalized" and "reverted", maintained by the indexer.
+// The indexer may have applied its own copy of this migration first, so every
+// step is skipped when already done.
+export async function up(knex: Knex): Promise<void> {
+  if (!(await knex.schema.hasColumn("blocks", "finality_status"))) {
+    await knex.schema.alterTable("blocks", (table) => {
+      table.string("finality_status").notNullable().defaultTo("unfinalized");
+    });
+  }
+  await knex.raw(
+    "CREATE INDEX IF NOT EXISTS blocks_unfinalized_number_idx ON blocks (number) WHERE finality_status = 'unfinalized';"
+  );
+}
+
+export async function down(knex: Knex): Promise<void> {
+  await knex.raw("DROP INDEX IF EXISTS blocks_unfinalized_number_idx;");
+  if (await knex.schema.hasColumn("blocks", "finality_status")) {
+    await knex.schema.alterTable("blocks", (table) => {
+      table.dropColumn("finality_status");
+    });
+  }
+}
@@ -0,0 +1,16 @@
+import { Knex } from "knex";
+
+// Key of log upserts in the indexer. Created as a plain unique index, the same
+// as the indexer's copy of this migration, so whichever runs second is a no-op.
+export async function up(knex: Knex): Promise<void> {
+  await knex.raw(
+    "CREATE UNIQUE INDEX IF NOT EXISTS logs_transaction_id_log_index_unique ON logs (transaction_id, log_index);"
+  );
+}
+
+export async function down(knex: Knex): Promise<void> {
+  await knex.raw(
+    "ALTER TABLE logs DROP CONSTRAINT IF EXISTS logs_transaction_id_log_index_unique;"
+  );
+  await knex.raw("DROP INDEX IF EXISTS logs_transaction_id_log_index_unique;");
+}
@@ -0,0 +1,26 @@
+import { Knex } from "knex";
+
+// Progress of the indexer, a single row written with every block
+export async function up(knex: Knex): Promise<void> {
+  await knex.raw(`
+    CREATE TABLE IF NOT EXISTS sync_state (
+      id smallint NOT NULL DEFAULT 1,
+      tip_number numeric NOT NULL,
+      tip_hash bytea NOT NULL,
+      updated_at timestamp with time zone NOT NULL,
+      indexer_version varchar(255),
+      chain_id bigint,
+      CONSTRAINT sync_state_pkey PRIMARY KEY (id),
+      CONSTRAINT sync_state_single_row CHECK (id = 1)
+    );
+  `);
+  await knex.raw(`
+    INSERT INTO sync_state (id, tip_number, tip_hash, updated_at)
+    SELECT 1, number, hash, now() FROM blocks ORDER BY number DESC LIMIT 1
+    ON CONFLICT (id) DO NOTHING;
+  `);
+}
+
+export async function down(knex: Knex): Promise<void> {
+  await knex.schema.dropTableIfExists("sync_state");
+}
@@ -0,0 +1,81 @@
+import { Knex } from "knex";
+
+// Rows reverted by a rollback of the indexer, kept for `orphan_retention_days`.
+// Tables already created by the indexer's copy of this migration are kept as is.
+export async function up(knex: Knex): Promise<void> {
+  if (!(await knex.schema.hasTable("orphan_blocks"))) {
+    await knex.schema.createTable(
+      "orphan_blocks",
+      function (table: Knex.TableBuilder) {
+        table.bigIncrements("orphan_id");
+        table.decimal("number", null, 0).notNullable().index();
+        table.binary("hash").notNullable().index();
+        table.binary("parent_hash").notNullable();
+        table.decimal("gas_limit", null, 0).notNullable();
+        table.decimal("gas_used", null, 0).notNullable();
+        table.binary("miner").notNullable();
+        table.integer("size").notNullable();
+        table.timestamp("timestamp").notNullable();
+        table.string("finality_status").notNullable();
+        table.timestamp("reverted_at").notNullable().index();
+        // Hash of the block indexed at the same number afterwards
+        table.binary("replaced_by_hash");
+      }
+    );
+  }
+  if (!(await knex.schema.hasTable("orphan_transactions"))) {
+    await knex.schema.createTable(
+      "orphan_transactions",
+      function (table: Knex.TableBuilder) {
+        table.bigIncrements("orphan_id");
+        table.bigInteger("id").notNullable();
+        table.binary("hash").notNullable().index();
+        table.binary("eth_tx_hash").notNullable().index();
+        table.decimal("block_number", null, 0).notNullable();
+        table.binary("block_hash").notNullable();
+        table.integer("transaction_index").notNullable();
+        table.binary("from_address").notNullable();
+        table.binary("to_address");
+        table.decimal("value", 80, 0).notNullable();
+        table.bigInteger("nonce").notNullable();
+        table.decimal("gas_limit", null, 0);
+        table.decimal("gas_price", null, 0);
+        table.binary("input");
+        table.smallint("v").notNullable();
+        table.binary("r").notNullable();
+        table.binary("s").notNullable();
+        table.decimal("cumulative_gas_used", null, 0);
+        table.decimal("gas_used", null, 0);
+        table.binary("contract_address");
+        table.smallint("exit_code").notNullable();
+        table.timestamp("reverted_at").notNullable().index();
+      }
+    );
+  }
+  if (!(await knex.schema.hasTable("orphan_logs"))) {
+    await knex.schema.createTable(
+      "orphan_logs",
+      function (table: Knex.TableBuilder) {
+        table.bigIncrements("orphan_id");
+        table.bigInteger("id").notNullable();
+        table.bigInteger("transaction_id").notNullable();
+        table.binary("transaction_hash").notNullable().index();
+        table.integer("transaction_index").notNullable();
+        table.decimal("block_number", null, 0).notNullable();
+        table.binary("block_hash").notNullable();
+        table.binary("address").notNullable();
+        table.binary("data");
+        table.integer("log_index").notNullable();
+        table.specificType("topics", "bytea ARRAY").notNullable();
+        table.timestamp("reverted_at").notNullable().index();
+      }
+    );
+  }
+}
+
+export async function down(knex: Knex): Promise<void> {
+  await knex.schema
+    .dropTableIfExists("orphan_logs")
+    .dropTableIfExists("orphan_transactions")
+    .dropTableIfExists("orphan_blocks");
+}
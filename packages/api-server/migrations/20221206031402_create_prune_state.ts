@@ -0,0 +1,18 @@
+import { Knex } from "knex";
+
+// Logs and transaction inputs of blocks below `pruned_below` are pruned by the indexer
+export async function up(knex: Knex): Promise<void> {
+  await knex.raw(`
+    CREATE TABLE IF NOT EXISTS prune_state (
+      id smallint NOT NULL DEFAULT 1,
+      pruned_below numeric NOT NULL,
+      updated_at timestamp with time zone NOT NULL,
+      CONSTRAINT prune_state_pkey PRIMARY KEY (id),
+      CONSTRAINT prune_state_single_row CHECK (id = 1)
+    );
+  `);
+}
+
+export async function down(knex: Knex): Promise<void> {
+  await knex.schema.dropTableIfExists("prune_state");
+}
@@ -0,0 +1,55 @@
+import { Knex } from "knex";
+
+// Withdrawal requests included in layer 2 blocks, reverted ones are archived like the
+// other orphans. Tables already created by the indexer's copy of this migration
+// are kept as is.
+export async function up(knex: Knex): Promise<void> {
+  if (!(await knex.schema.hasTable("withdrawals"))) {
+    await knex.schema.createTable(
+      "withdrawals",
+      function (table: Knex.TableBuilder) {
+        table.bigIncrements("id");
+        table.decimal("block_number", null, 0).notNullable();
+        table.binary("block_hash").notNullable().index();
+        table.integer("withdrawal_index").notNullable();
+        table.binary("account_script_hash").notNullable().index();
+        table.bigInteger("registry_id").notNullable();
+        // Null unless the account is an eth account
+        table.binary("address").index();
+        table.decimal("amount", null, 0).notNullable();
+        table.decimal("capacity", null, 0).notNullable();
+        table.binary("udt_script_hash").notNullable();
+        table.binary("owner_lock_hash").notNullable().index();
+        table.bigInteger("nonce").notNullable();
+        table.unique(["block_number", "withdrawal_index"]);
+      }
+    );
+  }
+  if (!(await knex.schema.hasTable("orphan_withdrawals"))) {
+    await knex.schema.createTable(
+      "orphan_withdrawals",
+      function (table: Knex.TableBuilder) {
+        table.bigIncrements("orphan_id");
+        table.bigInteger("id").notNullable();
+        table.decimal("block_number", null, 0).notNullable();
+        table.binary("block_hash").notNullable();
+        table.integer("withdrawal_index").notNullable();
+        table.binary("account_script_hash").notNullable();
+        table.bigInteger("registry_id").notNullable();
+        table.binary("address");
+        table.decimal("amount", null, 0).notNullable();
+        table.decimal("capacity", null, 0).notNullable();
+        table.binary("udt_script_hash").notNullable();
+        table.binary("owner_lock_hash").notNullable();
+        table.bigInteger("nonce").notNullable();
+        table.timestamp("reverted_at").notNullable().index();
+      }
+    );
+  }
+}
+
+export async function down(knex: Knex): Promise<void> {
+  await knex.schema
+    .dropTableIfExists("orphan_withdrawals")
+    .dropTableIfExists("withdrawals");
+}
@@ -0,0 +1,20 @@
+import { Knex } from "knex";
+
+// Fencing token of the indexer writer lock, bumped by every new holder and checked by
+// every write transaction. A table already created by the indexer's copy of this
+// migration is kept as is.
+export async function up(knex: Knex): Promise<void> {
+  await knex.raw(`
+    CREATE TABLE IF NOT EXISTS writer_fence (
+      id smallint NOT NULL DEFAULT 1,
+      token bigint NOT NULL,
+      updated_at timestamp with time zone NOT NULL,
+      CONSTRAINT writer_fence_pkey PRIMARY KEY (id),
+      CONSTRAINT writer_fence_single_row CHECK (id = 1)
+    );
+  `);
+}
+
+export async function down(knex: Knex): Promise<void> {
+  await knex.schema.dropTableIfExists("writer_fence");
+}
@@ -17,3 +17,6 @@ export const TIP_BLOCK_HASH_CACHE_EXPIRED_TIME_MS = 1000 * 60 * 5; // 5 minutes
 
 // knex db query cache time
 export const QUERY_CACHE_EXPIRED_TIME_MS = 1000 * 45; // 45 seconds ~= block produce interval
+
+// pruned below watermark cache time, it only moves up
+export const PRUNED_BELOW_CACHE_EXPIRED_TIME_MS = 1000 * 45; // 45 seconds
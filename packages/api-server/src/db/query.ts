@@ -12,6 +12,7 @@ import "./knex";
 import Knex, { knex, Knex as KnexType } from "knex";
 import { envConfig } from "../base/env-config";
 import { LATEST_MEDIAN_GAS_PRICE } from "./constant";
+import { PRUNED_BELOW_CACHE_EXPIRED_TIME_MS } from "../cache/constant";
 import {
   formatDecimal,
   toBigIntOpt,
@@ -26,7 +27,7 @@ import {
   buildQueryLogBlock,
   buildQueryLogId,
 } from "./helpers";
-import { LimitExceedError } from "../methods/error";
+import { LimitExceedError, PrunedDataError } from "../methods/error";
 import { FilterParams } from "../base/filter";
 import KnexTimeoutError = knex.KnexTimeoutError;
 import { logger } from "../base/logger";
@@ -41,6 +42,11 @@ const GLOBAL_KNEX = Knex({
   pool: { min: 2, max: +poolMax },
 });
 
+// Shared by all queries, checked by every request reading logs or transaction inputs
+let prunedBelowCache:
+  | { value: bigint | undefined; expiredAt: number }
+  | undefined;
+
 export class Query {
   private knex: KnexType;
 
@@ -84,19 +90,48 @@ export class Query {
     return bufferToHex(gwTxHash.hash);
   }
 
+  // The indexer moves the tip in the same transaction as each block it indexes, blocks
+  // backfilled above it are not served as the tip
   async getTipBlockNumber(): Promise<bigint | undefined> {
-    const blockData = await this.knex<DBBlock>("blocks")
-      .select("number")
-      .orderBy("number", "desc")
+    const syncState = await this.knex("sync_state")
+      .select("tip_number")
       .first()
       .cache();
 
-    return toBigIntOpt(blockData?.number);
+    return toBigIntOpt(syncState?.tip_number);
+  }
+
+  // Logs and transaction inputs of blocks below are pruned by the indexer
+  async getPrunedBelow(): Promise<bigint | undefined> {
+    const now = Date.now();
+    if (prunedBelowCache != null && prunedBelowCache.expiredAt > now) {
+      return prunedBelowCache.value;
+    }
+
+    const pruneState = await this.knex("prune_state")
+      .select("pruned_below")
+      .first();
+    const prunedBelow = toBigIntOpt(pruneState?.pruned_below);
+    prunedBelowCache = {
+      value: prunedBelow,
+      expiredAt: now + PRUNED_BELOW_CACHE_EXPIRED_TIME_MS,
+    };
+    return prunedBelow;
+  }
+
+  /**
+   * @throws PrunedDataError - logs and transaction inputs of the block are pruned.
+   */
+  async assertNotPruned(blockNumber: bigint): Promise<void> {
+    const prunedBelow = await this.getPrunedBelow();
+    if (prunedBelow != null && blockNumber < prunedBelow) {
+      throw new PrunedDataError(prunedBelow);
+    }
   }
 
   async getTipBlock(): Promise<Block | undefined> {
     const block = await this.knex<DBBlock>("blocks")
-      .orderBy("number", "desc")
+      .whereIn("number", this.knex("sync_state").select("tip_number"))
       .first()
       .cache();
 
@@ -323,6 +358,7 @@ export class Query {
 
   /**
    * @throws LimitExceedError - the number of queried results are over `MAX_QUERY_NUMBER`.
+   * @throws PrunedDataError - `fromBlock`, or the block of `blockHash`, is below the blocks whose logs are kept.
    */
   async getLogsByFilter(
     { addresses, topics, fromBlock, toBlock, blockHash }: FilterParams,
@@ -331,6 +367,15 @@ export class Query {
     const { MAX_QUERY_NUMBER, MAX_QUERY_TIME_MILSECS } =
       getDatabaseRateLimitingConfiguration();
 
+    if (blockHash != null) {
+      const block = await this.getBlockByHash(blockHash);
+      if (block != null) {
+        await this.assertNotPruned(block.number);
+      }
+    } else {
+      await this.assertNotPruned(fromBlock);
+    }
+
     // NOTE: In this SQL, there is no `ORDER BY id` as combining `ORDER BY` and `LIMIT` consumes too much time when the
     // results are large. Instead, logs are sorted outside the database:
     // - When the number of query results exceeds $MAX_QUERY_NUMBER, an error is returned.
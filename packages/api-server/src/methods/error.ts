@@ -8,6 +8,7 @@ import {
   INVALID_PARAMS,
   LIMIT_EXCEEDED,
   METHOD_NOT_SUPPORT,
+  RESOURCE_UNAVAILABLE,
   WEB3_ERROR,
 } from "./error-code";
 import { HexString } from "@ckb-lumos/base";
@@ -86,3 +87,13 @@ export class LimitExceedError extends RpcError {
     super(LIMIT_EXCEEDED, message);
   }
 }
+
+export class PrunedDataError extends RpcError {
+  constructor(prunedBelow: bigint) {
+    super(
+      RESOURCE_UNAVAILABLE,
+      `logs and transaction inputs of blocks below ${prunedBelow} are pruned`,
+      { prunedBelow: "0x" + prunedBelow.toString(16) }
+    );
+  }
+}
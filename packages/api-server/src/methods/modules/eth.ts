@@ -601,6 +601,7 @@ export class Eth {
       }
 
       if (isFullTransaction) {
+        await this.query.assertNotPruned(block.number);
         const txs = await this.query.getTransactionsByBlockHash(blockHash);
         const apiTxs = txs.map((tx) => toApiTransaction(tx));
         const apiBlock = toApiBlock(block, apiTxs);
@@ -637,6 +638,7 @@ export class Eth {
 
     const apiBlock = toApiBlock(block);
     if (isFullTransaction) {
+      await this.query.assertNotPruned(block.number);
       const txs = await this.query.getTransactionsByBlockNumber(blockNumber);
       const apiTxs = txs.map((tx) => toApiTransaction(tx));
       apiBlock.transactions = apiTxs;
@@ -723,6 +725,7 @@ export class Eth {
     // 1. Find in db
     const tx = await this.query.getTransactionByEthTxHash(ethTxHash);
     if (tx != null) {
+      await this.query.assertNotPruned(tx.block_number);
       // no need await
       // delete auto create account tx if already in db
       this.cacheStore.delete(cacheKey);
@@ -822,6 +825,7 @@ export class Eth {
     if (tx == null) {
       return null;
     }
+    await this.query.assertNotPruned(tx.block_number);
     const apiTx = toApiTransaction(tx);
     return apiTx;
   }
@@ -843,6 +847,7 @@ export class Eth {
     if (tx == null) {
       return null;
     }
+    await this.query.assertNotPruned(tx.block_number);
 
     const apiTx = toApiTransaction(tx);
     return apiTx;
@@ -864,6 +869,7 @@ export class Eth {
     const data = await this.query.getTransactionAndLogsByHash(gwTxHash);
     if (data != null) {
       const [tx, logs] = data;
+      await this.query.assertNotPruned(tx.block_number);
       const apiLogs = logs.map((log) => toApiLog(log, ethTxHash));
       const transactionReceipt = toApiTransactionReceipt(tx, apiLogs);
       return transactionReceipt;